@@ -0,0 +1,3 @@
+drop index if exists quest_comment_quest_post_created_at_idx;
+
+alter table quest_comment drop column if exists commenter_profile;
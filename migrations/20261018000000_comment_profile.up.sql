@@ -0,0 +1,6 @@
+-- Comments are shown under the profile that was active when they were posted.
+alter table quest_comment add column commenter_profile uuid references profile;
+
+comment on column quest_comment.commenter_profile is 'Profile the comment was posted as. Null if the profile no longer exists.';
+
+create index on quest_comment (quest_post, created_at);
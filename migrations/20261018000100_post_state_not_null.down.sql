@@ -0,0 +1,3 @@
+drop index if exists quest_post_quest_published_at_idx;
+
+alter table quest_post alter column state drop not null;
@@ -0,0 +1,5 @@
+-- Posts always have a lifecycle state.
+update quest_post set state = 'draft'::quest_post_state where state is null;
+alter table quest_post alter column state set not null;
+
+create index on quest_post (quest, published_at);
@@ -0,0 +1,6 @@
+drop table if exists quest_vote;
+
+alter table quest_post drop column if exists winning_command;
+alter table quest_post drop column if exists vote_mode;
+
+drop type if exists quest_vote_mode;
@@ -0,0 +1,31 @@
+-- How votes on the commands under a post are counted.
+create type quest_vote_mode as enum (
+  -- Each voter picks at most one command.
+  'single',
+  -- Each voter may vote for any number of commands.
+  'approval'
+);
+
+alter table quest_post add column vote_mode quest_vote_mode not null default 'single'::quest_vote_mode;
+alter table quest_post add column winning_command uuid references quest_comment;
+
+comment on column quest_post.vote_mode is 'How votes on commands are counted.';
+comment on column quest_post.winning_command is 'Command with the most votes when voting was closed. Null if there were no votes.';
+
+-- A vote by a reader for a command.
+create table quest_vote (
+  voter uuid references account not null,
+  quest_post uuid references quest_post not null,
+  command uuid references quest_comment not null,
+  created_at timestamptz not null default current_timestamp,
+  primary key (voter, command)
+);
+
+create index on quest_vote (quest_post, voter);
+create index on quest_vote (command);
+
+comment on table quest_vote is 'A vote for a command on a quest post.';
+comment on column quest_vote.voter is 'Account that voted.';
+comment on column quest_vote.quest_post is 'Quest post the command is under. Denormalized for checking single-choice votes.';
+comment on column quest_vote.command is 'Command being voted for.';
+comment on column quest_vote.created_at is 'When the vote was cast.';
@@ -0,0 +1,5 @@
+alter table account drop column if exists tech_role;
+alter table account drop column if exists admin_role;
+
+drop type if exists tech_role;
+drop type if exists admin_role;
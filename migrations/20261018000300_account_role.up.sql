@@ -0,0 +1,20 @@
+-- Moderation role of an account. Mirrors `AdminRole`.
+create type admin_role as enum (
+  'user',
+  'site_moderator',
+  'administrator'
+);
+
+-- Technical role of an account. Mirrors `TechRole`.
+create type tech_role as enum (
+  'user',
+  'operator'
+);
+
+alter table account add column admin_role admin_role not null default 'user'::admin_role;
+alter table account add column tech_role tech_role not null default 'user'::tech_role;
+
+create index on account (admin_role) where admin_role <> 'user'::admin_role;
+
+comment on column account.admin_role is 'Site moderation role. Administrators can appoint moderators.';
+comment on column account.tech_role is 'Technical role, e.g. site operator.';
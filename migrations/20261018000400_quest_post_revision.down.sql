@@ -0,0 +1,3 @@
+drop table if exists quest_post_revision;
+
+alter table quest_post drop column if exists edited_at;
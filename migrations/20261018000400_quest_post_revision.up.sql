@@ -0,0 +1,25 @@
+alter table quest_post add column edited_at timestamptz;
+
+comment on column quest_post.edited_at is 'When the post was last edited after being published. Null if never edited.';
+
+-- An earlier version of a quest post, saved whenever the post is edited.
+create table quest_post_revision (
+  id uuid primary key,
+  quest_post uuid references quest_post not null,
+  title text,
+  body_markup text not null,
+  body_html text not null,
+  created_at timestamptz not null,
+  replaced_at timestamptz not null default current_timestamp
+);
+
+create index on quest_post_revision (quest_post, replaced_at);
+
+comment on table quest_post_revision is 'An earlier version of a quest post.';
+comment on column quest_post_revision.id is 'Revision ID.';
+comment on column quest_post_revision.quest_post is 'Post this is a version of.';
+comment on column quest_post_revision.title is 'Title of the post at the time.';
+comment on column quest_post_revision.body_markup is 'Text of the post at the time, in markup form.';
+comment on column quest_post_revision.body_html is 'Text of the post at the time, converted to HTML.';
+comment on column quest_post_revision.created_at is 'When this version was written.';
+comment on column quest_post_revision.replaced_at is 'When this version was replaced by an edit.';
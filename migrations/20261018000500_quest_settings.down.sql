@@ -0,0 +1,21 @@
+alter table quest drop column if exists long_description_html;
+alter table quest drop column if exists short_description_html;
+
+-- Enum values can't be dropped, so recreate the type as it was.
+alter type quest_publish_state rename to quest_publish_state_old;
+create type quest_publish_state as enum (
+  'prepping',
+  'active',
+  'hiatus',
+  'cancelledcomplete'
+);
+alter table quest alter column publish_state drop default;
+alter table quest alter column publish_state type quest_publish_state using (
+  case publish_state::text
+    when 'cancelled' then 'cancelledcomplete'
+    when 'complete' then 'cancelledcomplete'
+    else publish_state::text
+  end
+)::quest_publish_state;
+alter table quest alter column publish_state set default 'prepping'::quest_publish_state;
+drop type quest_publish_state_old;
@@ -0,0 +1,10 @@
+-- The initial migration was missing a comma between 'cancelled' and
+-- 'complete', which created a single 'cancelledcomplete' value instead.
+alter type quest_publish_state rename value 'cancelledcomplete' to 'cancelled';
+alter type quest_publish_state add value 'complete';
+
+alter table quest add column short_description_html text;
+alter table quest add column long_description_html text;
+
+comment on column quest.short_description_html is 'Short description converted to HTML. Null if there is no short description.';
+comment on column quest.long_description_html is 'Long description converted to HTML. Null if there is no long description.';
@@ -0,0 +1,6 @@
+alter table quest_post drop column if exists chapter;
+
+drop table if exists quest_chapter;
+
+drop index if exists quest_post_quest_published_at_id_idx;
+create index on quest_post (quest, published_at);
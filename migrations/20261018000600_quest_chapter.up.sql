@@ -0,0 +1,24 @@
+-- Keyset pagination orders posts by (published_at, id).
+drop index if exists quest_post_quest_published_at_idx;
+create index on quest_post (quest, published_at, id);
+
+-- A named group of consecutive posts, e.g. a chapter or an arc.
+create table quest_chapter (
+  id uuid primary key,
+  quest uuid references quest not null,
+  title text not null constraint not_empty check (title <> ''),
+  created_at timestamptz not null default current_timestamp
+);
+
+create index on quest_chapter (quest);
+
+alter table quest_post add column chapter uuid references quest_chapter;
+
+create index on quest_post (chapter);
+
+comment on table quest_chapter is 'A chapter or arc of a quest, shown in its table of contents.';
+comment on column quest_chapter.id is 'Chapter ID.';
+comment on column quest_chapter.quest is 'What quest the chapter is part of.';
+comment on column quest_chapter.title is 'Title of the chapter.';
+comment on column quest_chapter.created_at is 'When the chapter was created.';
+comment on column quest_post.chapter is 'What chapter the post is part of, if any.';
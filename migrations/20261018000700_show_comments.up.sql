@@ -0,0 +1,3 @@
+alter table account add column show_comments boolean not null default true;
+
+comment on column account.show_comments is 'Setting to show comment threads between quest posts, as opposed to only the QM''s posts.';
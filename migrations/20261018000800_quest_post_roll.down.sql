@@ -0,0 +1 @@
+drop table if exists quest_post_roll;
@@ -0,0 +1,22 @@
+-- A dice roll made for a quest post. Rolls are never deleted, so that editing
+-- a post or restoring a revision reuses them instead of rolling again.
+create table quest_post_roll (
+  id uuid primary key,
+  quest_post uuid references quest_post not null,
+  expression text not null,
+  seed bigint not null,
+  dice integer[] not null,
+  total integer not null,
+  created_at timestamptz not null default current_timestamp
+);
+
+create index on quest_post_roll (quest_post, id);
+
+comment on table quest_post_roll is 'A dice roll made for a quest post.';
+comment on column quest_post_roll.id is 'Roll ID. Rolls for a post are matched to its markup in ID order.';
+comment on column quest_post_roll.quest_post is 'Post the roll was made for.';
+comment on column quest_post_roll.expression is 'What was rolled, e.g. 2d6+3.';
+comment on column quest_post_roll.seed is 'Seed the dice were generated from, so that the result can be verified.';
+comment on column quest_post_roll.dice is 'Result of each die.';
+comment on column quest_post_roll.total is 'Sum of the dice plus the modifier.';
+comment on column quest_post_roll.created_at is 'When the dice were rolled.';
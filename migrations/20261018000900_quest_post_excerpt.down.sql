@@ -0,0 +1 @@
+alter table quest_post drop column if exists excerpt;
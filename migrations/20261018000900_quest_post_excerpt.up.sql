@@ -0,0 +1,3 @@
+alter table quest_post add column excerpt text not null default '';
+
+comment on column quest_post.excerpt is 'Plain-text summary of the post for feeds and link previews, with spoilers hidden. Filled in when the post is saved.';
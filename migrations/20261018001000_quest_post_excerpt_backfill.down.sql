@@ -0,0 +1,5 @@
+update quest_post set excerpt = '' where excerpt is null;
+alter table quest_post alter column excerpt set default '';
+alter table quest_post alter column excerpt set not null;
+
+comment on column quest_post.excerpt is 'Plain-text summary of the post for feeds and link previews, with spoilers hidden. Filled in when the post is saved.';
@@ -0,0 +1,7 @@
+-- Excerpts can only be rendered by the app, so posts saved before they were
+-- stored are marked with a null excerpt and filled in at startup.
+alter table quest_post alter column excerpt drop not null;
+alter table quest_post alter column excerpt drop default;
+update quest_post set excerpt = null where excerpt = '';
+
+comment on column quest_post.excerpt is 'Plain-text summary of the post for feeds and link previews, with spoilers hidden. Filled in when the post is saved, or at startup if null.';
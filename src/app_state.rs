@@ -13,6 +13,8 @@ use uuid::Uuid;
 
 use crate::error::{Error, Result};
 use crate::key;
+use crate::markup;
+use crate::permissions::{self, AdminRole, TechRole};
 
 pub const SESSION_ID_COOKIE: &str = "sid";
 
@@ -22,6 +24,8 @@ pub struct SessionInfo {
     pub account_id: Uuid,
     pub session_id: String,
     pub current_profile: Option<ProfileRenderInfo>,
+    pub admin_role: AdminRole,
+    pub tech_role: TechRole,
 }
 
 /// Data necessary for rendering a page with a logged in user.
@@ -62,6 +66,30 @@ impl AppState {
         }
     }
 
+    /// Helper to get a user's session details that also requires that they
+    /// have at least the given moderation role.
+    pub async fn require_admin_role(
+        &self,
+        request: HttpRequest,
+        role: AdminRole,
+    ) -> Result<SessionInfo> {
+        let session_info = self.require_session(request).await?;
+        permissions::check_admin_role(session_info.admin_role, role)?;
+        Ok(session_info)
+    }
+
+    /// Helper to get a user's session details that also requires that they
+    /// have at least the given technical role.
+    pub async fn require_tech_role(
+        &self,
+        request: HttpRequest,
+        role: TechRole,
+    ) -> Result<SessionInfo> {
+        let session_info = self.require_session(request).await?;
+        permissions::check_tech_role(session_info.tech_role, role)?;
+        Ok(session_info)
+    }
+
     /// Helper function for clearing the server's session record. This has to be
     /// done if we notice it's corrupted in some way.
     fn background_clear_session(&self, session_id: &str) {
@@ -114,11 +142,36 @@ impl AppState {
                     })
                 });
 
+                // Roles are looked up every time rather than stored in the
+                // session, so that appointments take effect immediately.
+                let (admin_role, tech_role): (AdminRole, TechRole) = match sqlx::query_as(
+                    r#"
+                    select admin_role, tech_role
+                    from account
+                    where id = $1
+                    "#,
+                )
+                .bind(account_id)
+                .fetch_optional(&self.db_pool)
+                .await
+                .context("Failed to fetch account roles")?
+                {
+                    Some(roles) => roles,
+                    None => {
+                        self.background_clear_session(session_id);
+                        return Err(Error::AuthenticationError(
+                            "Your account no longer exists.".to_string(),
+                        ));
+                    }
+                };
+
                 Ok(SessionInfo {
                     raw,
                     account_id,
                     session_id: session_id.to_string(),
                     current_profile,
+                    admin_role,
+                    tech_role,
                 })
             }
             None => {
@@ -146,6 +199,8 @@ pub struct AppConfig {
     pub database_url: String,
     pub discord_app_id: String,
     pub discord_client_secret: String,
+    /// Hosts which quest markup may embed images from, besides this site.
+    pub image_hosts: Vec<String>,
     pub port: u16,
     pub redis_url: String,
     pub site_name: String,
@@ -155,5 +210,16 @@ pub struct AppConfig {
 pub fn config_with_defaults() -> std::result::Result<ConfigBuilder<DefaultState>, ConfigError> {
     Ok(Config::builder()
         .set_default("site_name", "Quest")?
-        .set_default("port", 8080)?)
+        .set_default("port", 8080)?
+        .set_default("image_hosts", Vec::<String>::new())?)
+}
+
+impl AppConfig {
+    /// Site settings used when rendering markup.
+    pub fn markup_options(&self) -> markup::Options<'_> {
+        markup::Options {
+            image_hosts: &self.image_hosts,
+            ..Default::default()
+        }
+    }
 }
@@ -24,12 +24,47 @@ pub enum Error {
     InternalError(#[from] anyhow::Error),
     AuthenticationError(String),
     AuthorizationError(String),
+    ForbiddenError(String),
+    NotFoundError(String),
     AppError(String),
 }
 
 /// Convenience alias.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// User-facing explanations for database constraints that can be violated by
+/// normal use of the site, as opposed to bugs.
+fn constraint_message(constraint: &str) -> Option<&'static str> {
+    match constraint {
+        "not_empty" => Some("The text can't be empty."),
+        "command_is_not_reply" => Some("Commands can't be replies to other comments."),
+        "exclusion_reason_only_if_excluded" => {
+            Some("An exclusion reason can only be given for excluded commands.")
+        }
+        _ => None,
+    }
+}
+
+/// Extension trait similar to anyhow's `Context`, but for database results.
+/// Known constraint violations become an `AppError` with a friendly message
+/// instead of an opaque internal error.
+pub trait DbContext<T> {
+    fn db_context(self, context: &'static str) -> Result<T>;
+}
+
+impl<T> DbContext<T> for std::result::Result<T, sqlx::Error> {
+    fn db_context(self, context: &'static str) -> Result<T> {
+        self.map_err(|err| {
+            if let sqlx::Error::Database(db_err) = &err {
+                if let Some(message) = db_err.constraint().and_then(constraint_message) {
+                    return Error::AppError(message.to_string());
+                }
+            }
+            Error::InternalError(anyhow::Error::new(err).context(context))
+        })
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if cfg!(debug_assertions) {
@@ -50,6 +85,12 @@ impl Display for Error {
                 Error::AuthorizationError(err) => {
                     write!(f, "{err}")?;
                 }
+                Error::ForbiddenError(err) => {
+                    write!(f, "{err}")?;
+                }
+                Error::NotFoundError(err) => {
+                    write!(f, "{err}")?;
+                }
                 Error::AppError(err) => {
                     write!(f, "{err}")?;
                 }
@@ -126,6 +167,8 @@ impl error::ResponseError for Error {
             Error::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::AuthenticationError(_) => StatusCode::BAD_REQUEST,
             Error::AuthorizationError(_) => StatusCode::UNAUTHORIZED,
+            Error::ForbiddenError(_) => StatusCode::FORBIDDEN,
+            Error::NotFoundError(_) => StatusCode::NOT_FOUND,
             Error::AppError(_) => StatusCode::BAD_REQUEST,
         }
     }
@@ -135,6 +178,11 @@ impl error::ResponseError for Error {
 pub fn custom_404<B>(
     res: actix_web::dev::ServiceResponse<B>,
 ) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    // Handlers that returned a NotFoundError already have a specific message.
+    if res.response().error().is_some() {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
     // Decompose the existing response, override the body, and reconstruct it.
     let (req, res) = res.into_parts();
     let res = res.set_body(
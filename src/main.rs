@@ -20,9 +20,11 @@ mod app_state;
 mod error;
 mod key;
 mod markup;
+mod model;
 mod oauth;
 mod partials;
 mod permissions;
+mod posts;
 mod routes;
 pub mod validation;
 
@@ -36,8 +38,10 @@ async fn main() -> Result<(), sqlx::Error> {
     let config: AppConfig = app_state::config_with_defaults()
         .unwrap()
         .add_source(
-            config::Environment::with_prefix("QUEST"), // .try_parsing(true)
-                                                       // .list_separator(","),
+            config::Environment::with_prefix("QUEST")
+                .try_parsing(true)
+                .list_separator(",")
+                .with_list_parse_key("image_hosts"),
         )
         .build()
         .expect("failed to build app config")
@@ -77,6 +81,10 @@ async fn main() -> Result<(), sqlx::Error> {
         uuid_seed,
     };
 
+    posts::backfill_excerpts(&app_state)
+        .await
+        .expect("failed to backfill post excerpts");
+
     let mut listenfd = ListenFd::from_env();
     let server = HttpServer::new(move || {
         let generated = generate();
@@ -0,0 +1,622 @@
+//! A typed representation of parsed markup.
+//!
+//! Parsing builds a [`Document`], which HTML rendering and anything else that
+//! needs to understand markup can walk without dealing with the grammar. It can
+//! be serialized, e.g. to cache it or to hand it to other tools.
+
+use pest::error::ErrorVariant;
+use pest::iterators::{Pair, Pairs};
+use serde::{Deserialize, Serialize};
+
+use super::dice::DiceExpr;
+use super::mention::Mention;
+use super::{html, image_error, is_allowed_image, is_safe_url, text, tidy};
+use super::{Options, ParseError, Rendered, Rule, Warning};
+
+/// A whole piece of markup, like a post.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+}
+
+/// Block-level content.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    /// Lines of text. Formatting doesn't continue from one line to the next.
+    Paragraph {
+        lines: Vec<Vec<Inline>>,
+    },
+    /// A heading, from level 1 for the largest to 5.
+    Heading {
+        level: usize,
+        content: Vec<Inline>,
+    },
+    Preformatted {
+        text: String,
+    },
+    Table(Table),
+    HorizontalRule,
+    /// A blockquote. A tight blockquote has no blank lines between its
+    /// blocks, so its paragraphs aren't set apart.
+    Blockquote {
+        tight: bool,
+        blocks: Vec<Block>,
+    },
+    List(List),
+    /// A collapsible section, with an optional summary to show in place of
+    /// "Spoiler".
+    Spoiler {
+        summary: Option<Vec<Inline>>,
+        blocks: Vec<Block>,
+    },
+    /// Markup which couldn't be rendered, to be shown as text.
+    Unrendered {
+        text: String,
+        warning: Warning,
+    },
+}
+
+/// A table, whose rows have a cell for every column.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Table {
+    pub alignments: Vec<Alignment>,
+    pub header: Vec<Vec<Inline>>,
+    pub rows: Vec<Vec<Vec<Inline>>>,
+}
+
+/// How the cells in a table column are aligned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    Default,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct List {
+    pub ordered: bool,
+    pub entries: Vec<ListEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListEntry {
+    /// An item, which starts with a paragraph. A tight item has no blank lines
+    /// between its blocks.
+    Item { tight: bool, blocks: Vec<Block> },
+    /// A list nested under the previous item.
+    List(List),
+}
+
+/// Content within a line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    /// Text as written, including any backslashes used to escape formatting.
+    Text {
+        text: String,
+    },
+    Format {
+        format: Format,
+        content: Vec<Inline>,
+    },
+    Code {
+        code: String,
+    },
+    Link {
+        text: String,
+        url: String,
+    },
+    /// A bare URL.
+    Autolink {
+        url: String,
+    },
+    Image {
+        alt: String,
+        url: String,
+    },
+    Dice {
+        expr: DiceExpr,
+    },
+    /// A mention, which might not be of a profile or quest that exists.
+    Mention {
+        mention: Mention,
+    },
+    /// Markup which couldn't be rendered, to be shown as text.
+    Unrendered {
+        text: String,
+        warning: Warning,
+    },
+}
+
+/// A kind of formatting, applied to text between two of its controls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+}
+
+impl Format {
+    /// Every format. Adding one here, to [`Format::control`] and to the
+    /// `control` rule in the grammar is all it takes to support it.
+    pub const ALL: [Format; 5] = [
+        Format::Bold,
+        Format::Italic,
+        Format::Underline,
+        Format::Strikethrough,
+        Format::Spoiler,
+    ];
+
+    /// The control which starts and ends the format.
+    pub fn control(self) -> &'static str {
+        match self {
+            Format::Bold => "**",
+            Format::Italic => "//",
+            Format::Underline => "__",
+            Format::Strikethrough => "~~",
+            Format::Spoiler => "||",
+        }
+    }
+
+    fn from_control(control: &str) -> Format {
+        Format::ALL
+            .into_iter()
+            .find(|format| format.control() == control)
+            .expect("every control has a format")
+    }
+}
+
+impl Document {
+    /// Render the document as HTML.
+    pub fn to_html(&self, options: &Options) -> Rendered {
+        html::render(self, options)
+    }
+
+    /// Render the document as plain text, with whitespace collapsed and
+    /// spoilers hidden.
+    pub fn to_text(&self, options: &Options) -> String {
+        text::render(self, options)
+    }
+
+    /// Write the document back as markup, in canonical form.
+    pub fn to_markup(&self) -> String {
+        tidy::write(self)
+    }
+
+    /// Plain text of the document, shortened to at most `max_chars`
+    /// characters.
+    pub fn to_excerpt(&self, options: &Options, max_chars: usize) -> String {
+        text::truncate(&self.to_text(options), max_chars)
+    }
+
+    /// Call `f` with every inline in the document, in order, including those
+    /// inside formatting.
+    pub fn for_each_inline(&self, f: &mut impl FnMut(&Inline)) {
+        for block in &self.blocks {
+            block_inlines(block, f);
+        }
+    }
+
+    /// The dice expressions in the document, in the order they're rolled.
+    pub fn dice(&self) -> Vec<DiceExpr> {
+        let mut dice = Vec::new();
+        self.for_each_inline(&mut |inline| {
+            if let Inline::Dice { expr } = inline {
+                dice.push(*expr);
+            }
+        });
+        dice
+    }
+
+    /// The mentions in the document, in order and without duplicates.
+    pub fn mentions(&self) -> Vec<Mention> {
+        let mut mentions: Vec<Mention> = Vec::new();
+        self.for_each_inline(&mut |inline| {
+            if let Inline::Mention { mention } = inline {
+                if !mentions.contains(mention) {
+                    mentions.push(mention.clone());
+                }
+            }
+        });
+        mentions
+    }
+}
+
+/// Call `f` with every inline in a block, as [`Document::for_each_inline`] does.
+pub(super) fn block_inlines(block: &Block, f: &mut impl FnMut(&Inline)) {
+    match block {
+        Block::Paragraph { lines } => lines.iter().for_each(|line| inlines(line, f)),
+        Block::Heading { content, .. } => inlines(content, f),
+        Block::Table(table) => {
+            let rows = std::iter::once(&table.header).chain(&table.rows);
+            rows.flatten().for_each(|cell| inlines(cell, f));
+        }
+        Block::Blockquote { blocks, .. } => blocks.iter().for_each(|block| block_inlines(block, f)),
+        Block::List(list) => list_inlines(list, f),
+        Block::Spoiler { summary, blocks } => {
+            if let Some(summary) = summary {
+                inlines(summary, f);
+            }
+            blocks.iter().for_each(|block| block_inlines(block, f));
+        }
+        Block::Preformatted { .. } | Block::HorizontalRule | Block::Unrendered { .. } => (),
+    }
+}
+
+fn list_inlines(list: &List, f: &mut impl FnMut(&Inline)) {
+    for entry in &list.entries {
+        match entry {
+            ListEntry::Item { blocks, .. } => {
+                blocks.iter().for_each(|block| block_inlines(block, f))
+            }
+            ListEntry::List(list) => list_inlines(list, f),
+        }
+    }
+}
+
+/// Call `f` with every inline in some content, as [`Document::for_each_inline`]
+/// does.
+pub(super) fn inlines(content: &[Inline], f: &mut impl FnMut(&Inline)) {
+    for inline in content {
+        f(inline);
+        if let Inline::Format { content, .. } = inline {
+            inlines(content, f);
+        }
+    }
+}
+
+/// Whether a blockquote or list item has no blank lines between its blocks.
+fn is_tight(container: &Pair<Rule>) -> bool {
+    !container
+        .clone()
+        .into_inner()
+        .any(|pair| pair.as_rule() == Rule::block_gap)
+}
+
+/// Alignment of a table column from its cell in the delimiter row.
+fn column_alignment(table_align: &str) -> Alignment {
+    let table_align = table_align.trim();
+    match (table_align.starts_with(':'), table_align.ends_with(':')) {
+        (false, false) => Alignment::Default,
+        (true, false) => Alignment::Left,
+        (true, true) => Alignment::Center,
+        (false, true) => Alignment::Right,
+    }
+}
+
+/// The cells of a table row, skipping the end of input if the row is last.
+fn table_cells(row: Pair<Rule>) -> Vec<Pair<Rule>> {
+    row.into_inner()
+        .filter(|pair| pair.as_rule() == Rule::table_cell)
+        .collect()
+}
+
+/// Add an inline to some content, joining it to any text before it.
+fn push_inline(content: &mut Vec<Inline>, inline: Inline) {
+    if let Inline::Text { text } = &inline {
+        if let Some(Inline::Text { text: previous }) = content.last_mut() {
+            previous.push_str(text);
+            return;
+        }
+    }
+    content.push(inline);
+}
+
+/// What a formatting control does, once it's been matched up.
+#[derive(Clone, Copy)]
+enum ControlRole {
+    Literal,
+    Open(Format),
+    Close,
+}
+
+/// Builds a [`Document`] from parsed markup.
+pub(super) struct Builder<'o, 'a> {
+    options: &'o Options<'a>,
+    /// Whether to keep markup which can't be rendered as text, with a warning,
+    /// rather than failing.
+    tolerant: bool,
+    /// Line of the whole document that the parsed markup starts at.
+    first_line: usize,
+}
+
+impl<'o, 'a> Builder<'o, 'a> {
+    pub(super) fn new(options: &'o Options<'a>, tolerant: bool, first_line: usize) -> Self {
+        Builder {
+            options,
+            tolerant,
+            first_line,
+        }
+    }
+
+    /// Deal with a part of the markup which can't be rendered. Strictly, that's
+    /// an error. Tolerantly, it's kept as text, with a warning.
+    fn unrendered(
+        &self,
+        err: ParseError,
+        pair: &Pair<Rule>,
+    ) -> Result<(String, Warning), ParseError> {
+        if !self.tolerant {
+            return Err(err);
+        }
+        let text = pair.as_str().trim_end_matches(['\r', '\n']).to_string();
+        Ok((text, Warning::new(&err, self.first_line)))
+    }
+
+    /// Build the blocks among some pairs, skipping anything else.
+    pub(super) fn blocks(&self, pairs: Pairs<Rule>) -> Result<Vec<Block>, ParseError> {
+        let mut blocks = Vec::new();
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::document => blocks.extend(self.blocks(pair.into_inner())?),
+                Rule::paragraph | Rule::list_paragraph => blocks.push(Block::Paragraph {
+                    lines: pair
+                        .into_inner()
+                        .filter(|line| {
+                            matches!(line.as_rule(), Rule::paragraph_line | Rule::list_line)
+                        })
+                        .map(|line| self.inlines(line.into_inner()))
+                        .collect::<Result<_, _>>()?,
+                }),
+                Rule::heading => {
+                    let mut inner = pair.into_inner();
+                    let level = inner.next().expect("heading has a level").as_str().len();
+                    let line = inner.next().expect("heading has a line");
+                    blocks.push(Block::Heading {
+                        level,
+                        content: self.inlines(line.into_inner())?,
+                    });
+                }
+                Rule::preformatted => {
+                    // Each line keeps its newline, except the last, which is
+                    // followed by the closing fence.
+                    let text: String = pair.into_inner().map(|line| line.as_str()).collect();
+                    let text = text
+                        .strip_suffix('\n')
+                        .map(|text| text.strip_suffix('\r').unwrap_or(text))
+                        .or_else(|| text.strip_suffix('\r'))
+                        .unwrap_or(&text);
+                    blocks.push(Block::Preformatted {
+                        text: text.to_string(),
+                    });
+                }
+                Rule::table => blocks.push(match self.table(pair.clone()) {
+                    Ok(table) => Block::Table(table),
+                    Err(err) => {
+                        let (text, warning) = self.unrendered(err, &pair)?;
+                        Block::Unrendered { text, warning }
+                    }
+                }),
+                Rule::horizontal_rule => blocks.push(Block::HorizontalRule),
+                Rule::blockquote => blocks.push(Block::Blockquote {
+                    tight: is_tight(&pair),
+                    blocks: self.blocks(pair.into_inner())?,
+                }),
+                Rule::unordered_list | Rule::ordered_list => {
+                    blocks.push(Block::List(self.list(pair)?))
+                }
+                Rule::spoiler_block => {
+                    let summary = pair
+                        .clone()
+                        .into_inner()
+                        .find(|inner| inner.as_rule() == Rule::spoiler_summary)
+                        .map(|summary| self.inlines(summary.into_inner()))
+                        .transpose()?;
+                    blocks.push(Block::Spoiler {
+                        summary,
+                        blocks: self.blocks(pair.into_inner())?,
+                    });
+                }
+                _ => (),
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn list(&self, pair: Pair<Rule>) -> Result<List, ParseError> {
+        let ordered = matches!(pair.as_rule(), Rule::ordered_list | Rule::ol_nested);
+        let mut entries = Vec::new();
+        for inner in pair.into_inner() {
+            match inner.as_rule() {
+                Rule::list_item => entries.push(ListEntry::Item {
+                    tight: is_tight(&inner),
+                    blocks: self.blocks(inner.into_inner())?,
+                }),
+                Rule::ul_nested | Rule::ol_nested => {
+                    entries.push(ListEntry::List(self.list(inner)?))
+                }
+                _ => (),
+            }
+        }
+        Ok(List { ordered, entries })
+    }
+
+    /// Check that a table is well-formed and build it.
+    fn table(&self, table: Pair<Rule>) -> Result<Table, ParseError> {
+        let mut children = table.into_inner();
+        let header = children.next().expect("table has a header");
+        let delimiter = children.next().expect("table has a delimiter row");
+        let alignments: Vec<Alignment> = delimiter
+            .clone()
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::table_align)
+            .map(|table_align| column_alignment(table_align.as_str()))
+            .collect();
+        let header_cells = table_cells(header);
+        if header_cells.len() != alignments.len() {
+            return Err(ParseError::new_from_span(
+                ErrorVariant::CustomError {
+                    message: format!(
+                        "The table header has {} columns, but the row under it has {}.",
+                        header_cells.len(),
+                        alignments.len()
+                    ),
+                },
+                delimiter.as_span(),
+            ));
+        }
+        let header = self.table_row(header_cells)?;
+
+        let mut rows = Vec::new();
+        for row in
+            children.filter(|pair| matches!(pair.as_rule(), Rule::table_row | Rule::table_bad_row))
+        {
+            if row.as_rule() == Rule::table_bad_row {
+                return Err(ParseError::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "Table rows must start and end with \"|\".".to_string(),
+                    },
+                    row.as_span(),
+                ));
+            }
+            let span = row.as_span();
+            let cells = table_cells(row);
+            if cells.len() != alignments.len() {
+                return Err(ParseError::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: format!(
+                            "This row has {} cells, but the table has {} columns.",
+                            cells.len(),
+                            alignments.len()
+                        ),
+                    },
+                    span,
+                ));
+            }
+            rows.push(self.table_row(cells)?);
+        }
+        Ok(Table {
+            alignments,
+            header,
+            rows,
+        })
+    }
+
+    /// Build the cells of a table row, dropping whitespace between their
+    /// content and the pipe after them.
+    fn table_row(&self, cells: Vec<Pair<Rule>>) -> Result<Vec<Vec<Inline>>, ParseError> {
+        cells
+            .into_iter()
+            .map(|cell| {
+                let mut content = self.inlines(cell.into_inner())?;
+                if let Some(Inline::Text { text }) = content.last_mut() {
+                    text.truncate(text.trim_end().len());
+                    if text.is_empty() {
+                        content.pop();
+                    }
+                }
+                Ok(content)
+            })
+            .collect()
+    }
+
+    /// Build a run of inline content, matching up its formatting controls.
+    ///
+    /// A control closes the most recent open control of the same kind, as long
+    /// as there's something between them, and any controls opened after that
+    /// one are left unclosed. Unclosed controls are kept as text.
+    fn inlines(&self, pairs: Pairs<Rule>) -> Result<Vec<Inline>, ParseError> {
+        let pairs: Vec<Pair<Rule>> = pairs.filter(|pair| pair.as_rule() != Rule::EOI).collect();
+        let mut roles = vec![ControlRole::Literal; pairs.len()];
+        // Index and format of each open control, innermost last.
+        let mut open: Vec<(usize, Format)> = Vec::new();
+        for (i, pair) in pairs.iter().enumerate() {
+            if pair.as_rule() != Rule::control {
+                continue;
+            }
+            let format = Format::from_control(pair.as_str());
+            match open
+                .iter()
+                .rposition(|&(_, open_format)| open_format == format)
+            {
+                Some(position) if open[position].0 + 1 < i => {
+                    roles[open[position].0] = ControlRole::Open(format);
+                    roles[i] = ControlRole::Close;
+                    open.truncate(position);
+                }
+                _ => open.push((i, format)),
+            }
+        }
+
+        // Content of each open format, and the content around it.
+        let mut outer: Vec<(Format, Vec<Inline>)> = Vec::new();
+        let mut content = Vec::new();
+        for (pair, role) in pairs.into_iter().zip(roles) {
+            match role {
+                ControlRole::Open(format) => outer.push((format, std::mem::take(&mut content))),
+                ControlRole::Close => {
+                    let (format, outer_content) = outer.pop().expect("close has an open");
+                    let inner = std::mem::replace(&mut content, outer_content);
+                    content.push(Inline::Format {
+                        format,
+                        content: inner,
+                    });
+                }
+                ControlRole::Literal => push_inline(&mut content, self.inline(pair)?),
+            }
+        }
+        Ok(content)
+    }
+
+    fn inline(&self, pair: Pair<Rule>) -> Result<Inline, ParseError> {
+        Ok(match pair.as_rule() {
+            Rule::text | Rule::text_pipe | Rule::control => Inline::Text {
+                text: pair.as_str().to_string(),
+            },
+            Rule::code_span => Inline::Code {
+                code: pair.as_str().trim_matches('`').to_string(),
+            },
+            Rule::link => {
+                let mut inner = pair.clone().into_inner();
+                let text = inner.next().expect("link has text").as_str();
+                let url = inner.next().expect("link has a URL").as_str();
+                if is_safe_url(url) {
+                    Inline::Link {
+                        text: text.to_string(),
+                        url: url.to_string(),
+                    }
+                } else {
+                    Inline::Text {
+                        text: pair.as_str().to_string(),
+                    }
+                }
+            }
+            Rule::autolink => Inline::Autolink {
+                url: pair.as_str().to_string(),
+            },
+            Rule::image => {
+                let mut inner = pair.clone().into_inner();
+                let alt = inner.next().expect("image has alt text").as_str();
+                let url = inner.next().expect("image has a URL").as_str();
+                if is_allowed_image(url, self.options) {
+                    Inline::Image {
+                        alt: alt.to_string(),
+                        url: url.to_string(),
+                    }
+                } else {
+                    let (text, warning) =
+                        self.unrendered(image_error(&pair, self.options), &pair)?;
+                    Inline::Unrendered { text, warning }
+                }
+            }
+            Rule::dice => match DiceExpr::from_pair(&pair) {
+                Ok(expr) => Inline::Dice { expr },
+                Err(err) => {
+                    let (text, warning) = self.unrendered(err, &pair)?;
+                    Inline::Unrendered { text, warning }
+                }
+            },
+            Rule::mention => Inline::Mention {
+                mention: Mention::from_pair(&pair),
+            },
+            rule => unreachable!("{rule:?} isn't inline content"),
+        })
+    }
+}
@@ -0,0 +1,173 @@
+//! Dice rolls, like `[[roll 2d6+3]]`.
+//!
+//! Rolls are resolved when a post is saved and stored alongside it, so they
+//! never change when the post is edited or rendered again. Each roll is
+//! generated from a stored seed with a fixed algorithm, so the server can
+//! check that a stored result is what the seed produces.
+
+use pest::error::ErrorVariant;
+use pest::iterators::Pair;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::{ParseError, Rule};
+
+/// Most dice in a single roll.
+pub const MAX_COUNT: u32 = 100;
+/// Most sides a die can have.
+pub const MAX_SIDES: u32 = 1000;
+/// Largest modifier which can be added to or subtracted from a roll.
+pub const MAX_MODIFIER: i32 = 10_000;
+
+/// A dice expression, like `2d6+3`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiceExpr {
+    pub count: u32,
+    pub sides: u32,
+    pub modifier: i32,
+}
+
+impl fmt::Display for DiceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}d{}", self.count, self.sides)?;
+        match self.modifier {
+            0 => Ok(()),
+            modifier if modifier > 0 => write!(f, "+{modifier}"),
+            modifier => write!(f, "{modifier}"),
+        }
+    }
+}
+
+impl DiceExpr {
+    /// Reads an expression from a `dice` pair, checking that it's in range.
+    pub(super) fn from_pair(pair: &Pair<Rule>) -> Result<DiceExpr, ParseError> {
+        let error = |message: String| {
+            ParseError::new_from_span(ErrorVariant::CustomError { message }, pair.as_span())
+        };
+        let mut count = 1;
+        let mut sides = 0;
+        let mut modifier = 0;
+        for inner in pair.clone().into_inner() {
+            // Anything too long to parse is out of range anyway.
+            match inner.as_rule() {
+                Rule::dice_count => count = inner.as_str().parse().unwrap_or(u32::MAX),
+                Rule::dice_sides => sides = inner.as_str().parse().unwrap_or(u32::MAX),
+                Rule::dice_modifier => {
+                    let (sign, amount) = inner.as_str().split_at(1);
+                    let amount: i32 = amount.trim().parse().unwrap_or(i32::MAX);
+                    modifier = if sign == "-" { -amount } else { amount };
+                }
+                _ => (),
+            }
+        }
+        if !(1..=MAX_COUNT).contains(&count) {
+            return Err(error(format!(
+                "A roll must have between 1 and {MAX_COUNT} dice."
+            )));
+        }
+        if !(2..=MAX_SIDES).contains(&sides) {
+            return Err(error(format!(
+                "Dice must have between 2 and {MAX_SIDES} sides."
+            )));
+        }
+        if modifier.abs() > MAX_MODIFIER {
+            return Err(error(format!(
+                "Roll modifiers can be at most {MAX_MODIFIER}."
+            )));
+        }
+        Ok(DiceExpr {
+            count,
+            sides,
+            modifier,
+        })
+    }
+}
+
+/// The result of rolling a dice expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiceRoll {
+    /// The expression rolled, as formatted by [`DiceExpr`].
+    pub expression: String,
+    pub seed: i64,
+    pub dice: Vec<i32>,
+    pub total: i32,
+}
+
+impl DiceRoll {
+    /// Rolls an expression. The same expression and seed always give the same
+    /// result.
+    pub fn roll(expr: &DiceExpr, seed: i64) -> DiceRoll {
+        let mut state = seed as u64;
+        let dice: Vec<i32> = (0..expr.count)
+            .map(|_| roll_die(&mut state, expr.sides) as i32)
+            .collect();
+        let total = dice.iter().sum::<i32>() + expr.modifier;
+        DiceRoll {
+            expression: expr.to_string(),
+            seed,
+            dice,
+            total,
+        }
+    }
+
+    /// Whether the result is what the seed produces for the expression.
+    pub fn verify(&self, expr: &DiceExpr) -> bool {
+        *self == DiceRoll::roll(expr, self.seed)
+    }
+}
+
+/// SplitMix64, chosen because it's tiny and fully specified, so that stored
+/// rolls can be checked regardless of library versions.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Rolls a die with no bias towards lower numbers.
+fn roll_die(state: &mut u64, sides: u32) -> u32 {
+    let sides = sides as u64;
+    let zone = u64::MAX - (u64::MAX % sides);
+    loop {
+        let value = next_random(state);
+        if value < zone {
+            return (value % sides) as u32 + 1;
+        }
+    }
+}
+
+/// Matches the dice expressions in a post to rolls already stored for it, so
+/// that editing a post keeps its results. The n-th roll of an expression reuses
+/// the n-th stored roll of that expression, and anything left over is rolled
+/// with a seed from `new_seed`. Returns every roll, in order, and which of them
+/// are new.
+pub fn assign(
+    expressions: &[DiceExpr],
+    stored: &[DiceRoll],
+    mut new_seed: impl FnMut() -> i64,
+) -> (Vec<DiceRoll>, Vec<DiceRoll>) {
+    let mut used = vec![false; stored.len()];
+    let mut rolls = Vec::with_capacity(expressions.len());
+    let mut new_rolls = Vec::new();
+    for expr in expressions {
+        let expression = expr.to_string();
+        let reused = stored
+            .iter()
+            .enumerate()
+            .find(|(i, roll)| !used[*i] && roll.expression == expression);
+        match reused {
+            Some((i, roll)) => {
+                used[i] = true;
+                rolls.push(roll.clone());
+            }
+            None => {
+                let roll = DiceRoll::roll(expr, new_seed());
+                new_rolls.push(roll.clone());
+                rolls.push(roll);
+            }
+        }
+    }
+    (rolls, new_rolls)
+}
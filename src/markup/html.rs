@@ -0,0 +1,285 @@
+//! Rendering a [`Document`] as HTML.
+
+use askama_escape::{escape, Html};
+use std::fmt::Write;
+
+use super::ast::{Alignment, Block, Document, Format, Inline, List, ListEntry};
+use super::dice::{DiceExpr, DiceRoll};
+use super::{Options, Rendered, Warning};
+
+/// Attributes added to every link, since links come from user content.
+const LINK_REL: &str = "nofollow ugc noopener";
+
+/// Opening and closing tags for each heading level.
+const HEADING_TAGS: [(&str, &str); 5] = [
+    ("<h2>", "</h2>"),
+    ("<h3>", "</h3>"),
+    ("<h4>", "</h4>"),
+    ("<h5>", "</h5>"),
+    ("<h6>", "</h6>"),
+];
+
+/// Opening and closing tags for a format.
+fn format_tags(format: Format) -> (&'static str, &'static str) {
+    match format {
+        Format::Bold => ("<strong>", "</strong>"),
+        Format::Italic => ("<em>", "</em>"),
+        Format::Underline => ("<ins>", "</ins>"),
+        Format::Strikethrough => ("<del>", "</del>"),
+        // Focusable, so that clicking it reveals it without any scripts.
+        Format::Spoiler => ("<span class=\"spoiler\" tabindex=\"0\">", "</span>"),
+    }
+}
+
+/// Opening tag for a table cell.
+fn cell_tag(cell: &str, alignment: Alignment) -> String {
+    match alignment {
+        Alignment::Default => format!("<{cell}>"),
+        Alignment::Left => format!("<{cell} style=\"text-align: left\">"),
+        Alignment::Center => format!("<{cell} style=\"text-align: center\">"),
+        Alignment::Right => format!("<{cell} style=\"text-align: right\">"),
+    }
+}
+
+/// Render a document, filling in dice rolls and linking mentions from the
+/// options.
+pub(super) fn render(document: &Document, options: &Options) -> Rendered {
+    let mut writer = Writer {
+        options,
+        rolls: options.rolls.iter(),
+        rendered: Rendered::default(),
+    };
+    writer.blocks(&document.blocks, false);
+    writer.rendered
+}
+
+struct Writer<'o, 'a> {
+    options: &'o Options<'a>,
+    rolls: std::slice::Iter<'a, DiceRoll>,
+    rendered: Rendered,
+}
+
+impl Writer<'_, '_> {
+    fn push(&mut self, str: &str) {
+        self.rendered.html.push_str(str);
+    }
+
+    fn escaped(&mut self, text: &str) {
+        write!(self.rendered.html, "{}", escape(text, Html)).expect("escaping can't fail");
+    }
+
+    /// Write blocks, leaving out paragraph tags if they're in a tight
+    /// container.
+    fn blocks(&mut self, blocks: &[Block], tight: bool) {
+        for block in blocks {
+            self.block(block, tight);
+        }
+    }
+
+    fn block(&mut self, block: &Block, tight: bool) {
+        match block {
+            Block::Paragraph { lines } => {
+                if !tight {
+                    self.push("<p>");
+                }
+                for (i, line) in lines.iter().enumerate() {
+                    if i > 0 {
+                        self.push("\n");
+                    }
+                    self.inlines(line);
+                }
+                if !tight {
+                    self.push("</p>");
+                }
+            }
+            Block::Heading { level, content } => {
+                // "#" is <h2>, since the page title is the <h1>.
+                let (open, close) = HEADING_TAGS[(*level).clamp(1, 5) - 1];
+                self.push(open);
+                self.inlines(content);
+                self.push(close);
+            }
+            Block::Preformatted { text } => {
+                self.push("<pre>");
+                self.escaped(text);
+                self.push("</pre>");
+            }
+            Block::Table(table) => {
+                self.push("<table><thead><tr>");
+                for (cell, &alignment) in table.header.iter().zip(&table.alignments) {
+                    self.push(&cell_tag("th", alignment));
+                    self.inlines(cell);
+                    self.push("</th>");
+                }
+                self.push("</tr></thead>");
+                if !table.rows.is_empty() {
+                    self.push("<tbody>");
+                    for row in &table.rows {
+                        self.push("<tr>");
+                        for (cell, &alignment) in row.iter().zip(&table.alignments) {
+                            self.push(&cell_tag("td", alignment));
+                            self.inlines(cell);
+                            self.push("</td>");
+                        }
+                        self.push("</tr>");
+                    }
+                    self.push("</tbody>");
+                }
+                self.push("</table>");
+            }
+            Block::HorizontalRule => self.push("<hr />"),
+            Block::Blockquote { tight, blocks } => {
+                self.push("<blockquote>");
+                self.blocks(blocks, *tight);
+                self.push("</blockquote>");
+            }
+            Block::List(list) => self.list(list),
+            Block::Spoiler { summary, blocks } => {
+                self.push("<details><summary>");
+                match summary {
+                    Some(summary) => self.inlines(summary),
+                    None => self.push("Spoiler"),
+                }
+                self.push("</summary>");
+                // Always separate paragraphs, since there's no marker to set
+                // the contents apart.
+                self.blocks(blocks, false);
+                self.push("</details>");
+            }
+            Block::Unrendered { text, warning } => {
+                self.push("<p>");
+                self.unrendered(text, warning);
+                self.push("</p>");
+            }
+        }
+    }
+
+    fn list(&mut self, list: &List) {
+        let (open, close) = if list.ordered {
+            ("<ol>", "</ol>")
+        } else {
+            ("<ul>", "</ul>")
+        };
+        self.push(open);
+        for entry in &list.entries {
+            match entry {
+                ListEntry::Item { tight, blocks } => {
+                    self.push("<li>");
+                    self.blocks(blocks, *tight);
+                    self.push("</li>");
+                }
+                ListEntry::List(list) => self.list(list),
+            }
+        }
+        self.push(close);
+    }
+
+    fn inlines(&mut self, content: &[Inline]) {
+        for inline in content {
+            self.inline(inline);
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline) {
+        match inline {
+            Inline::Text { text } => self.escaped(text),
+            Inline::Format { format, content } => {
+                let (open, close) = format_tags(*format);
+                self.push(open);
+                self.inlines(content);
+                self.push(close);
+            }
+            Inline::Code { code } => {
+                self.push("<code>");
+                self.escaped(code);
+                self.push("</code>");
+            }
+            Inline::Link { text, url } => self.link(url, text),
+            Inline::Autolink { url } => self.link(url, url),
+            Inline::Image { alt, url } => write!(
+                self.rendered.html,
+                "<img src=\"{}\" alt=\"{}\" loading=\"lazy\" />",
+                escape(url, Html),
+                escape(alt, Html)
+            )
+            .expect("escaping can't fail"),
+            Inline::Dice { expr } => {
+                let roll = self.rolls.next();
+                self.dice(expr, roll);
+            }
+            Inline::Mention { mention } => {
+                if self.options.mentions.contains(mention) {
+                    write!(
+                        self.rendered.html,
+                        "<a href=\"{}\" class=\"mention\">{}</a>",
+                        escape(&mention.href(), Html),
+                        escape(&mention.to_string(), Html)
+                    )
+                    .expect("escaping can't fail");
+                    if !self.rendered.mentions.contains(mention) {
+                        self.rendered.mentions.push(mention.clone());
+                    }
+                } else {
+                    self.escaped(&mention.to_string());
+                }
+            }
+            Inline::Unrendered { text, warning } => self.unrendered(text, warning),
+        }
+    }
+
+    /// Write an anchor element with an escaped `href` and text.
+    fn link(&mut self, url: &str, text: &str) {
+        write!(
+            self.rendered.html,
+            "<a href=\"{}\" rel=\"{}\">{}</a>",
+            escape(url, Html),
+            LINK_REL,
+            escape(text, Html)
+        )
+        .expect("escaping can't fail");
+    }
+
+    /// Write a dice roll, or a placeholder if it hasn't been rolled.
+    fn dice(&mut self, expr: &DiceExpr, roll: Option<&DiceRoll>) {
+        let expression = expr.to_string();
+        let Some(roll) = roll.filter(|roll| roll.expression == expression) else {
+            write!(
+                self.rendered.html,
+                "<span class=\"dice\">{expression}: <em>rolled when posted</em></span>"
+            )
+            .expect("writing to a string can't fail");
+            return;
+        };
+        let dice: Vec<String> = roll.dice.iter().map(i32::to_string).collect();
+        let modifier = match expr.modifier {
+            0 => String::new(),
+            modifier if modifier > 0 => format!(" + {modifier}"),
+            modifier => format!(" - {}", -modifier),
+        };
+        write!(
+            self.rendered.html,
+            "<span class=\"dice\" data-seed=\"{}\">{expression}: [{}]{modifier} = <strong>{}</strong></span>",
+            roll.seed,
+            dice.join(", "),
+            roll.total
+        )
+        .expect("writing to a string can't fail");
+    }
+
+    /// Write markup which couldn't be rendered as text, keeping the warning
+    /// about it.
+    fn unrendered(&mut self, text: &str, warning: &Warning) {
+        if self.options.mark_warnings {
+            write!(
+                self.rendered.html,
+                "<mark class=\"markup-warning\" title=\"{}\">{}</mark>",
+                escape(&warning.to_string(), Html),
+                escape(text, Html)
+            )
+            .expect("escaping can't fail");
+        } else {
+            self.escaped(text);
+        }
+        self.rendered.warnings.push(warning.clone());
+    }
+}
@@ -0,0 +1,64 @@
+//! Mentions of profiles, like `@alice`, and references to quests, like
+//! `@alice/dungeon`.
+//!
+//! The markup doesn't know which profiles and quests exist, so callers look up
+//! the mentions found by [`super::mentions`] and pass the ones which exist back
+//! in [`super::Options`]. Unknown mentions are left as plain text.
+
+use pest::iterators::Pair;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::Rule;
+
+/// A mention of a profile or a quest.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Mention {
+    Profile { username: String },
+    Quest { username: String, slug: String },
+}
+
+impl Mention {
+    /// Reads a mention from a `mention` pair.
+    pub(super) fn from_pair(pair: &Pair<Rule>) -> Mention {
+        let mut username = String::new();
+        let mut slug = None;
+        for inner in pair.clone().into_inner() {
+            match inner.as_rule() {
+                Rule::mention_username => username = inner.as_str().to_string(),
+                Rule::mention_slug => slug = Some(inner.as_str().to_string()),
+                _ => (),
+            }
+        }
+        match slug {
+            Some(slug) => Mention::Quest { username, slug },
+            None => Mention::Profile { username },
+        }
+    }
+
+    /// The profile that's mentioned, or that runs the quest.
+    pub fn username(&self) -> &str {
+        match self {
+            Mention::Profile { username } | Mention::Quest { username, .. } => username,
+        }
+    }
+
+    /// Where the mention links to.
+    pub fn href(&self) -> String {
+        match self {
+            Mention::Profile { username } => format!("/@{username}"),
+            Mention::Quest { username, slug } => format!("/@{username}/{slug}"),
+        }
+    }
+}
+
+impl fmt::Display for Mention {
+    /// The mention as it's written in markup.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mention::Profile { username } => write!(f, "@{username}"),
+            Mention::Quest { username, slug } => write!(f, "@{username}/{slug}"),
+        }
+    }
+}
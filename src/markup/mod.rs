@@ -1,11 +1,24 @@
-use askama_escape::{escape, Html};
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
 use pest::iterators::Pair;
 use pest::iterators::Pairs;
 use pest::Parser;
 use pest_derive::Parser;
-use std::fmt::Write;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
+use crate::validation::is_site_path;
+
+pub mod ast;
+pub mod dice;
+mod html;
+pub mod mention;
 mod pretty_debug;
+mod text;
+mod tidy;
+
+use ast::{Block, Builder, Document};
+use dice::{DiceExpr, DiceRoll};
+use mention::Mention;
 
 #[derive(Parser)]
 #[grammar = "markup/markup.pest"]
@@ -14,20 +27,254 @@ pub struct MarkupParser;
 /// Convenience type for parsing errors.
 pub type ParseError = pest::error::Error<Rule>;
 /// Convenience type for the result of parsing.
-pub type ParseResult = Result<String, ParseError>;
-
-enum TraversalState<'a> {
-    Prequeued(Pairs<'a, Rule>),
-    Enter(Pair<'a, Rule>),
-    FormatClose,
-    BlockquoteClose,
-    OutputChar(char),
-    OutputStr(&'a str),
+pub type ParseResult = Result<Rendered, ParseError>;
+
+/// Markup converted to HTML, along with what was found in it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rendered {
+    pub html: String,
+    /// The mentions which were linked, in the order they first appear.
+    pub mentions: Vec<Mention>,
+    /// Problems in the markup, the parts with problems having been shown as
+    /// text instead. Only [`to_html_tolerant`] renders markup with problems.
+    pub warnings: Vec<Warning>,
+}
+
+/// A part of some markup which couldn't be rendered, and why.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub message: String,
+    /// Line and column where the part starts, counting from 1.
+    pub start: (usize, usize),
+    /// Line and column where the part ends.
+    pub end: (usize, usize),
+}
+
+impl Warning {
+    /// Describe an error in markup whose first line is line `first_line` of
+    /// the document.
+    fn new(err: &ParseError, first_line: usize) -> Warning {
+        let message = match &err.variant {
+            ErrorVariant::CustomError { message } => message.clone(),
+            variant => variant.message().into_owned(),
+        };
+        let (start, end) = match err.line_col {
+            LineColLocation::Pos(pos) => (pos, pos),
+            LineColLocation::Span(start, end) => (start, end),
+        };
+        let in_document = |(line, column): (usize, usize)| (line + first_line - 1, column);
+        Warning {
+            message,
+            start: in_document(start),
+            end: in_document(end),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Line {}, column {}: {}",
+            self.start.0, self.start.1, self.message
+        )
+    }
+}
+
+/// URL schemes which may be linked to. Anything else (`javascript:`, `data:`,
+/// ...) is rendered as plain text instead.
+const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Whether a link target is safe to put in an `href`. Site-relative paths and
+/// fragments are allowed, as are absolute URLs with a scheme in
+/// [`SAFE_URL_SCHEMES`].
+fn is_safe_url(url: &str) -> bool {
+    if url.starts_with('#') || is_site_path(url) {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((scheme, _)) => SAFE_URL_SCHEMES
+            .iter()
+            .any(|safe| safe.eq_ignore_ascii_case(scheme)),
+        None => false,
+    }
+}
+
+/// Site-specific settings which affect rendering.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options<'a> {
+    /// Hosts which images may be embedded from, in addition to images on this
+    /// site.
+    pub image_hosts: &'a [String],
+    /// Results for the dice rolls in the markup, in order. Rolls without a
+    /// result are shown as not rolled yet, e.g. in previews.
+    pub rolls: &'a [DiceRoll],
+    /// Mentions of profiles and quests which exist. Any others are rendered
+    /// as plain text.
+    pub mentions: &'a [Mention],
+    /// Whether to highlight markup which is shown as text because it couldn't
+    /// be rendered, e.g. in previews.
+    pub mark_warnings: bool,
+}
+
+/// Whether an image may be embedded. Site-relative paths are always allowed;
+/// absolute URLs must be http(s) and on one of the allowed hosts.
+fn is_allowed_image(url: &str, options: &Options) -> bool {
+    if is_site_path(url) {
+        return true;
+    }
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return false;
+    };
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return false;
+    }
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    // Credentials in the URL could be used to disguise the real host.
+    if authority.contains('@') {
+        return false;
+    }
+    let host = authority.split(':').next().unwrap_or_default();
+    options
+        .image_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// The error for an image from a host which isn't allowed.
+fn image_error(pair: &Pair<Rule>, options: &Options) -> ParseError {
+    let message = if options.image_hosts.is_empty() {
+        "Images must be uploaded to this site.".to_string()
+    } else {
+        format!(
+            "Images must be uploaded to this site or come from one of: {}.",
+            options.image_hosts.join(", ")
+        )
+    };
+    ParseError::new_from_span(ErrorVariant::CustomError { message }, pair.as_span())
+}
+
+/// Part of a document, which is parsed separately in tolerant mode.
+enum Region<'a> {
+    Parsed {
+        pairs: Pairs<'a, Rule>,
+        first_line: usize,
+    },
+    /// A line which doesn't parse, to be shown as text.
+    Unparsed { text: &'a str, warning: Warning },
+}
+
+/// Parse a document, splitting off any lines which don't parse so that the
+/// rest of it can still be rendered. The document starts at line `first_line`.
+fn parse_regions(markup: &str, first_line: usize) -> Vec<Region<'_>> {
+    let err = match MarkupParser::parse(Rule::document, markup) {
+        Ok(pairs) => return vec![Region::Parsed { pairs, first_line }],
+        Err(err) => err,
+    };
+    let warning = Warning::new(&err, first_line);
+    let position = match err.location {
+        InputLocation::Pos(position) | InputLocation::Span((position, _)) => position,
+    };
+    let start = markup[..position].rfind('\n').map_or(0, |i| i + 1);
+    let end = markup[position..]
+        .find('\n')
+        .map_or(markup.len(), |i| position + i + 1);
+    if start >= end {
+        return vec![Region::Unparsed {
+            text: markup,
+            warning,
+        }];
+    }
+
+    let mut regions = Vec::new();
+    if start > 0 {
+        regions.extend(parse_regions(&markup[..start], first_line));
+    }
+    regions.push(Region::Unparsed {
+        text: &markup[start..end],
+        warning,
+    });
+    if end < markup.len() {
+        let next_line = first_line + markup[..end].matches('\n').count();
+        regions.extend(parse_regions(&markup[end..], next_line));
+    }
+    regions
+}
+
+/// Parse markup into a [`Document`]. Anything which can't be rendered is an
+/// error.
+pub fn parse(markup: &str, options: &Options) -> Result<Document, ParseError> {
+    parse_pairs(MarkupParser::parse(Rule::document, markup)?, options)
+}
+
+/// Parse markup into a [`Document`], keeping any parts which can't be rendered
+/// as text, with warnings about them.
+pub fn parse_tolerant(markup: &str, options: &Options) -> Document {
+    let mut blocks = Vec::new();
+    for region in parse_regions(markup, 1) {
+        match region {
+            Region::Parsed { pairs, first_line } => blocks.extend(
+                Builder::new(options, true, first_line)
+                    .blocks(pairs)
+                    .expect("tolerant parsing can't fail"),
+            ),
+            Region::Unparsed { text, warning } => blocks.push(Block::Unrendered {
+                text: text.trim_end_matches(['\r', '\n']).to_string(),
+                warning,
+            }),
+        }
+    }
+    Document { blocks }
+}
+
+fn parse_pairs(pairs: Pairs<Rule>, options: &Options) -> Result<Document, ParseError> {
+    Ok(Document {
+        blocks: Builder::new(options, false, 1).blocks(pairs)?,
+    })
+}
+
+/// Return the dice expressions in the given markup, in order, so that they can
+/// be rolled before rendering. Invalid expressions are skipped, since they're
+/// never rolled.
+pub fn dice_expressions(markup: &str) -> Vec<DiceExpr> {
+    parse_tolerant(markup, &Options::default()).dice()
+}
+
+/// Return the mentions in the given markup, in order and without duplicates,
+/// so that they can be looked up before rendering.
+pub fn mentions(markup: &str) -> Vec<Mention> {
+    parse_tolerant(markup, &Options::default()).mentions()
 }
 
 /// Return an HTML representation of the given markup.
-pub fn to_html(markup: &str) -> ParseResult {
-    Ok(parsed_to_html(MarkupParser::parse(Rule::document, markup)?))
+pub fn to_html(markup: &str, options: &Options) -> ParseResult {
+    Ok(parse(markup, options)?.to_html(options))
+}
+
+/// Return an HTML representation of the given markup, showing any parts which
+/// can't be rendered as text and returning warnings about them instead of
+/// failing.
+pub fn to_html_tolerant(markup: &str, options: &Options) -> Rendered {
+    parse_tolerant(markup, options).to_html(options)
+}
+
+/// Return the given markup as plain text, without formatting or spoilers, for
+/// places which can't show HTML.
+pub fn to_text(markup: &str, options: &Options) -> String {
+    parse_tolerant(markup, options).to_text(options)
+}
+
+/// Return a plain-text summary of the given markup, shortened to at most
+/// `max_chars` characters at a word boundary.
+pub fn to_excerpt(markup: &str, options: &Options, max_chars: usize) -> String {
+    parse_tolerant(markup, options).to_excerpt(options, max_chars)
+}
+
+/// Return the given markup in canonical form, with consistent list markers,
+/// blockquote prefixes and spacing. Tidying doesn't change how the markup
+/// renders, and tidying it again doesn't change it further.
+pub fn tidy(markup: &str) -> String {
+    tidy::tidy(markup)
 }
 
 /// Return an debugging representation of the given markup.
@@ -38,140 +285,9 @@ pub fn to_debug(markup: &str) -> String {
     }
 }
 
-fn parsed_to_html(pairs: Pairs<Rule>) -> String {
-    let mut html = String::new();
-    let mut stack: Vec<TraversalState> = Vec::new();
-    stack.push(TraversalState::Prequeued(pairs));
-    let mut format_stack: Vec<&str> = Vec::new();
-    let mut blockquote_last_depth: usize = 0;
-
-    while stack.len() > 0 {
-        let state = match stack.pop() {
-            Some(tup) => tup,
-            None => unreachable!(),
-        };
-
-        match state {
-            TraversalState::Prequeued(pairs) => {
-                let mut iter = pairs.into_iter().rev().peekable();
-                while let Some(pair) = iter.next() {
-                    let lf = match pair.as_rule() {
-                        Rule::paragraph_line | Rule::list_line => iter.peek().is_some(),
-                        // blockquote_depth is the first child of blockquote and
-                        // doesn't count.
-                        Rule::blockquote_line => iter
-                            .peek()
-                            .is_some_and(|next_pair| next_pair.as_rule() == Rule::blockquote_line),
-                        _ => false,
-                    };
-                    stack.push(TraversalState::Enter(pair));
-                    if lf {
-                        stack.push(TraversalState::OutputChar('\n'));
-                    }
-                }
-            }
-            TraversalState::OutputChar(ch) => {
-                html.push(ch);
-            }
-            TraversalState::OutputStr(str) => {
-                html.push_str(str);
-            }
-            TraversalState::Enter(pair) => {
-                let rule = pair.as_rule();
-                match rule {
-                    Rule::paragraph => {
-                        html.push_str("<p>");
-                        stack.push(TraversalState::OutputStr("</p>"));
-                    }
-                    Rule::unordered_list | Rule::ul_nested => {
-                        html.push_str("<ul>");
-                        stack.push(TraversalState::OutputStr("</ul>"));
-                    }
-                    Rule::ordered_list | Rule::ol_nested => {
-                        html.push_str("<ol>");
-                        stack.push(TraversalState::OutputStr("</ol>"));
-                    }
-                    Rule::list_item => {
-                        html.push_str("<li>");
-                        stack.push(TraversalState::OutputStr("</li>"));
-                    }
-                    Rule::control => match pair.as_str() {
-                        "//" => {
-                            html.push_str("<em>");
-                            format_stack.push("</em>");
-                        }
-                        "**" => {
-                            html.push_str("<strong>");
-                            format_stack.push("</strong>");
-                        }
-                        "__" => {
-                            html.push_str("<ins>");
-                            format_stack.push("</ins>");
-                        }
-                        "~~" => {
-                            html.push_str("<del>");
-                            format_stack.push("</del>");
-                        }
-                        "||" => {
-                            // Placeholder.
-                            html.push_str("<span class=\"spoiler\">");
-                            format_stack.push("</span>");
-                        }
-                        str => {
-                            panic!("Unknown format control string: {}", str);
-                        }
-                    },
-                    Rule::blockquote_depth => {
-                        let depth = pair.as_str().len();
-                        if blockquote_last_depth < depth {
-                            for _ in 0..(depth - blockquote_last_depth) {
-                                html.push_str("<blockquote>");
-                            }
-                        } else {
-                            for _ in 0..(blockquote_last_depth - depth) {
-                                html.push_str("</blockquote>");
-                            }
-                        }
-                        blockquote_last_depth = depth;
-                    }
-                    Rule::blockquote => {
-                        stack.push(TraversalState::BlockquoteClose);
-                    }
-                    Rule::horizontal_rule => {
-                        html.push_str("<hr />");
-                    }
-                    Rule::text => {
-                        write!(&mut html, "{}", escape(pair.as_str(), Html))
-                            .expect("escaping can't fail");
-                    }
-                    Rule::formatted
-                    | Rule::formatted2
-                    | Rule::formatted3
-                    | Rule::formatted4
-                    | Rule::formatted5
-                    | Rule::formatted6 => {
-                        stack.push(TraversalState::FormatClose);
-                    }
-                    _ => (),
-                }
-                let inner = pair.into_inner();
-                if inner.len() > 0 {
-                    stack.push(TraversalState::Prequeued(inner));
-                }
-            }
-            TraversalState::FormatClose => {
-                html.push_str(format_stack.pop().expect("Expected closing format string"));
-            }
-            TraversalState::BlockquoteClose => {
-                for _ in 0..blockquote_last_depth {
-                    html.push_str("</blockquote>");
-                }
-                blockquote_last_depth = 0;
-            }
-        }
-    }
-
-    html
+#[cfg(test)]
+fn parsed_to_html(pairs: Pairs<Rule>, options: &Options) -> ParseResult {
+    Ok(parse_pairs(pairs, options)?.to_html(options))
 }
 
 #[cfg(test)]
@@ -185,17 +301,75 @@ mod tests {
     // part of the API contract and the actual measure of correctness is the
     // HTML output.
 
+    /// Hosts which images may come from in tests.
+    fn test_image_hosts() -> Vec<String> {
+        vec!["images.example.com".to_string()]
+    }
+
+    /// Profiles and quests which exist in tests.
+    fn test_mentions() -> Vec<Mention> {
+        vec![
+            Mention::Profile {
+                username: "alice".to_string(),
+            },
+            Mention::Quest {
+                username: "alice".to_string(),
+                slug: "dungeon".to_string(),
+            },
+        ]
+    }
+
+    /// Check that rendering and tidying some markup is quick. Rules which scan
+    /// to the end of the line before failing make long lines of them take
+    /// quadratic time, which shows up as seconds or minutes here.
+    fn assert_fast(markup: &str) {
+        let start = std::time::Instant::now();
+        to_html_tolerant(markup, &Options::default());
+        tidy(markup);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "took {elapsed:?}"
+        );
+    }
+
     /// Macro to check that an assertion is true. It's a macro to not throw off
     /// stack traces in test failures.
     macro_rules! assert_html {
-        ($input:expr, $output:expr $(,)?) => {{
+        ($input:expr, $output:expr $(,)?) => {
+            assert_html!($input, $output, rolls: &[])
+        };
+        ($input:expr, $output:expr, rolls: $rolls:expr $(,)?) => {{
             let parse_tree = MarkupParser::parse(Rule::document, $input)?;
             eprintln!("{}", pretty_debug::stack_based(parse_tree.clone()));
-            assert_eq!(parsed_to_html(parse_tree), $output);
+            let image_hosts = test_image_hosts();
+            let mentions = test_mentions();
+            let options = Options {
+                image_hosts: &image_hosts,
+                rolls: $rolls,
+                mentions: &mentions,
+                ..Default::default()
+            };
+            assert_eq!(parsed_to_html(parse_tree, &options)?.html, $output);
             Ok(())
         }};
     }
 
+    /// Macro to check that rendering fails with an error mentioning the given
+    /// text.
+    macro_rules! assert_error {
+        ($input:expr, $message:expr $(,)?) => {{
+            let image_hosts = test_image_hosts();
+            let options = Options {
+                image_hosts: &image_hosts,
+                ..Default::default()
+            };
+            let err = to_html($input, &options).expect_err("expected an error");
+            let text = format!("{}", err);
+            assert!(text.contains($message), "unexpected error: {}", text);
+        }};
+    }
+
     mod format {
         use super::*;
         #[test]
@@ -227,13 +401,70 @@ mod tests {
         fn spoiler_basic() -> TestResult {
             assert_html!(
                 "||spoiler||",
-                "<p><span class=\"spoiler\">spoiler</span></p>"
+                "<p><span class=\"spoiler\" tabindex=\"0\">spoiler</span></p>"
             )
         }
 
         #[test]
         fn max_nested() -> TestResult {
-            assert_html!("**bold //italic __underline ~~strikethrough~~ ||spoiler text spoiler|| ~~strikethrough~~ underline__ italic// bold**", "<p><strong>bold <em>italic <ins>underline <del>strikethrough</del> <span class=\"spoiler\">spoiler text spoiler</span> <del>strikethrough</del> underline</ins> italic</em> bold</strong></p>")
+            assert_html!("**bold //italic __underline ~~strikethrough~~ ||spoiler text spoiler|| ~~strikethrough~~ underline__ italic// bold**", "<p><strong>bold <em>italic <ins>underline <del>strikethrough</del> <span class=\"spoiler\" tabindex=\"0\">spoiler text spoiler</span> <del>strikethrough</del> underline</ins> italic</em> bold</strong></p>")
+        }
+
+        #[test]
+        fn nested_any_order() -> TestResult {
+            assert_html!(
+                "||~~__//**deep**//__~~||",
+                "<p><span class=\"spoiler\" tabindex=\"0\"><del><ins><em><strong>deep</strong></em></ins></del></span></p>",
+            )
+        }
+
+        #[test]
+        fn separate_runs() -> TestResult {
+            assert_html!(
+                "**a** and **b**",
+                "<p><strong>a</strong> and <strong>b</strong></p>",
+            )
+        }
+
+        #[test]
+        fn unclosed() -> TestResult {
+            assert_html!("**bold", "<p>**bold</p>")
+        }
+
+        #[test]
+        fn unclosed_inside() -> TestResult {
+            assert_html!(
+                "**bold //not italic**",
+                "<p><strong>bold //not italic</strong></p>",
+            )
+        }
+
+        #[test]
+        fn overlapping() -> TestResult {
+            assert_html!("**a //b** c//", "<p><strong>a //b</strong> c//</p>",)
+        }
+
+        #[test]
+        fn empty() -> TestResult {
+            assert_html!("****", "<p>****</p>")
+        }
+
+        #[test]
+        fn empty_then_closed() -> TestResult {
+            assert_html!("****bold**", "<p>**<strong>bold</strong></p>")
+        }
+
+        #[test]
+        fn not_across_lines() -> TestResult {
+            assert_html!("**a\nb**", "<p>**a\nb**</p>")
+        }
+
+        #[test]
+        fn with_inline_content() -> TestResult {
+            assert_html!(
+                "//`code` @alice [[roll d4]]//",
+                "<p><em><code>code</code> <a href=\"/@alice\" class=\"mention\">@alice</a> <span class=\"dice\">1d4: <em>rolled when posted</em></span></em></p>",
+            )
         }
 
         #[test]
@@ -250,344 +481,1853 @@ mod tests {
         }
     }
 
-    mod horizontal_rule {
+    mod heading {
         use super::*;
 
         #[test]
         fn basic() -> TestResult {
-            assert_html!("---", "<hr />")
+            assert_html!("# Chapter One", "<h2>Chapter One</h2>")
         }
 
         #[test]
-        fn broken() -> TestResult {
-            assert_html!("  --- ---   ", "<hr />")
+        fn levels() -> TestResult {
+            assert_html!(
+                r#"## two
+### three
+#### four
+##### five"#,
+                "<h3>two</h3><h4>three</h4><h5>four</h5><h6>five</h6>",
+            )
         }
 
         #[test]
-        fn weird() -> TestResult {
-            assert_html!(" - -- ---", "<hr />")
+        fn too_deep() -> TestResult {
+            assert_html!("###### six", "<p>###### six</p>")
+        }
+
+        #[test]
+        fn needs_space() -> TestResult {
+            assert_html!("#hashtag", "<p>#hashtag</p>")
+        }
+
+        #[test]
+        fn formatted() -> TestResult {
+            assert_html!("# The **Dragon**", "<h2>The <strong>Dragon</strong></h2>")
+        }
+
+        #[test]
+        fn between_paragraphs() -> TestResult {
+            assert_html!(
+                r#"before
+# Heading
+after"#,
+                "<p>before</p><h2>Heading</h2><p>after</p>",
+            )
+        }
+
+        #[test]
+        fn escaped() -> TestResult {
+            assert_html!("# <b>", "<h2>&lt;b&gt;</h2>")
         }
     }
 
-    mod paragraph {
+    mod code {
         use super::*;
 
         #[test]
-        fn multi() -> TestResult {
+        fn span() -> TestResult {
+            assert_html!("use `look around`", "<p>use <code>look around</code></p>")
+        }
+
+        #[test]
+        fn span_no_formatting() -> TestResult {
             assert_html!(
-                r#"hello
+                "`**not bold** //or italic// https://example.com`",
+                "<p><code>**not bold** //or italic// https://example.com</code></p>",
+            )
+        }
 
-world"#,
-                "<p>hello</p><p>world</p>",
+        #[test]
+        fn span_escaped() -> TestResult {
+            assert_html!("`<b>&`", "<p><code>&lt;b&gt;&amp;</code></p>")
+        }
+
+        #[test]
+        fn span_in_formatting() -> TestResult {
+            assert_html!("**a `b` c**", "<p><strong>a <code>b</code> c</strong></p>")
+        }
+
+        #[test]
+        fn unclosed_span() -> TestResult {
+            assert_html!("a ` b", "<p>a ` b</p>")
+        }
+
+        #[test]
+        fn preformatted() -> TestResult {
+            assert_html!(
+                r#"```
+STR  10
+DEX   8
+```"#,
+                "<pre>STR  10\nDEX   8</pre>",
             )
         }
 
         #[test]
-        fn joined() -> TestResult {
+        fn preformatted_whitespace() -> TestResult {
             assert_html!(
-                r#"hello
-world"#,
-                r#"<p>hello
-world</p>"#,
+                "```\n\n  +--+\n  |  |  \n\n  +--+\n```",
+                "<pre>\n  +--+\n  |  |  \n\n  +--+</pre>",
             )
         }
 
         #[test]
-        fn padded() -> TestResult {
+        fn preformatted_no_formatting() -> TestResult {
             assert_html!(
-                r#"
+                "```\n**a** //b// ~~c~~ [d](/e) > f\n- g\n# h\n```",
+                "<pre>**a** //b// ~~c~~ [d](/e) &gt; f\n- g\n# h</pre>",
+            )
+        }
 
-hello world
+        #[test]
+        fn preformatted_empty() -> TestResult {
+            assert_html!("```\n```", "<pre></pre>")
+        }
 
+        #[test]
+        fn preformatted_crlf() -> TestResult {
+            assert_html!("```\r\na\r\nb\r\n```", "<pre>a\r\nb</pre>")
+        }
 
-"#,
-                "<p>hello world</p>",
+        #[test]
+        fn preformatted_between_paragraphs() -> TestResult {
+            assert_html!(
+                "before\n```\n  map\n```\nafter",
+                "<p>before</p><pre>  map</pre><p>after</p>",
             )
         }
+
+        #[test]
+        fn unclosed_preformatted() -> TestResult {
+            assert_html!("```\nhello", "<p>```\nhello</p>")
+        }
     }
 
-    mod blockquote {
+    mod table {
         use super::*;
 
         #[test]
         fn basic() -> TestResult {
-            assert_html!("> hello", "<blockquote>hello</blockquote>")
+            assert_html!(
+                r#"| Stat | Value |
+|------|-------|
+| STR  | 10    |
+| DEX  | 8     |"#,
+                "<table><thead><tr><th>Stat</th><th>Value</th></tr></thead><tbody><tr><td>STR</td><td>10</td></tr><tr><td>DEX</td><td>8</td></tr></tbody></table>",
+            )
         }
 
         #[test]
-        fn formatted() -> TestResult {
+        fn header_only() -> TestResult {
             assert_html!(
-                "> hello //world//",
-                "<blockquote>hello <em>world</em></blockquote>",
+                "| a | b |\n| - | - |",
+                "<table><thead><tr><th>a</th><th>b</th></tr></thead></table>",
             )
         }
 
         #[test]
-        fn continued() -> TestResult {
+        fn alignment() -> TestResult {
             assert_html!(
-                r#"> hello
-> continued"#,
-                r#"<blockquote>hello
-continued</blockquote>"#,
+                r#"| none | left | center | right |
+| --- | :-- | :-: | --: |
+| a | b | c | d |"#,
+                "<table><thead><tr><th>none</th><th style=\"text-align: left\">left</th><th style=\"text-align: center\">center</th><th style=\"text-align: right\">right</th></tr></thead><tbody><tr><td>a</td><td style=\"text-align: left\">b</td><td style=\"text-align: center\">c</td><td style=\"text-align: right\">d</td></tr></tbody></table>",
             )
         }
 
         #[test]
-        fn strip_leading() -> TestResult {
+        fn formatted_cells() -> TestResult {
             assert_html!(
-                r#"  > hello
- > continued"#,
-                r#"<blockquote>hello
-continued</blockquote>"#,
+                r#"| Item | Notes |
+|---|---|
+| **Sword** | //cursed//, see [wiki](/wiki) |
+| `rope` | |"#,
+                "<table><thead><tr><th>Item</th><th>Notes</th></tr></thead><tbody><tr><td><strong>Sword</strong></td><td><em>cursed</em>, see <a href=\"/wiki\" rel=\"nofollow ugc noopener\">wiki</a></td></tr><tr><td><code>rope</code></td><td></td></tr></tbody></table>",
             )
         }
 
         #[test]
-        #[ignore]
-        fn multi_paragraph() -> TestResult {
+        fn escaped_cells() -> TestResult {
             assert_html!(
-                r#"> hello
->
-> continued"#,
-                r#"<blockquote><p>hello</p><p>continued</p></blockquote>"#,
+                "| <a> |\n|---|\n| & |",
+                "<table><thead><tr><th>&lt;a&gt;</th></tr></thead><tbody><tr><td>&amp;</td></tr></tbody></table>",
             )
         }
 
         #[test]
-        fn basic_nesting() -> TestResult {
+        fn between_paragraphs() -> TestResult {
             assert_html!(
-                r#"> hello
-> continued
->> nested"#,
-                r#"<blockquote>hello
-continued<blockquote>nested</blockquote></blockquote>"#,
+                "before\n| a |\n|---|\n| b |\n\nafter",
+                "<p>before</p><table><thead><tr><th>a</th></tr></thead><tbody><tr><td>b</td></tr></tbody></table><p>after</p>",
             )
         }
 
         #[test]
-        fn basic_return() -> TestResult {
+        fn pipes_in_paragraph() -> TestResult {
+            assert_html!("a | b |c|", "<p>a | b |c|</p>")
+        }
+
+        #[test]
+        fn spoiler_with_pipe() -> TestResult {
             assert_html!(
-                r#"> hello
-> continued
->> nested
-> return to original level"#,
-                r#"<blockquote>hello
-continued<blockquote>nested</blockquote>return to original level</blockquote>"#,
+                "||a|b||",
+                "<p><span class=\"spoiler\" tabindex=\"0\">a|b</span></p>",
             )
         }
 
         #[test]
-        fn nested_many() -> TestResult {
-            assert_html!(
-                ">>>>>>>>>>>>>> hello",
-                "<blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote>hello</blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote>",
+        fn no_delimiter_row() -> TestResult {
+            assert_html!("| a | b |\n| c | d |", "<p>| a | b |\n| c | d |</p>")
+        }
+
+        #[test]
+        fn too_many_cells() {
+            assert_error!(
+                "| a | b |\n|---|---|\n| 1 | 2 |\n| 1 | 2 | 3 |",
+                "This row has 3 cells, but the table has 2 columns.",
+            );
+            assert_error!("| a | b |\n|---|---|\n| 1 | 2 |\n| 1 | 2 | 3 |", "--> 4:1");
+        }
+
+        #[test]
+        fn too_few_cells() {
+            assert_error!(
+                "| a | b |\n|---|---|\n| 1 |",
+                "This row has 1 cells, but the table has 2 columns.",
             )
         }
 
         #[test]
-        fn triangular() -> TestResult {
-            assert_html!(
-                r#"> 1
->> 2
->>> 3
->>>> 4
->>>>> 5
->>>> 4
->>> 3
->> 2
-> 1
-"#,
-                "<blockquote>1<blockquote>2<blockquote>3<blockquote>4<blockquote>5</blockquote>4</blockquote>3</blockquote>2</blockquote>1</blockquote>",
+        fn unterminated_row() {
+            assert_error!(
+                "| a | b |\n|---|---|\n| 1 | 2",
+                "Table rows must start and end with \"|\".",
+            );
+            assert_error!("| a | b |\n|---|---|\n| 1 | 2", "--> 3:1");
+        }
+
+        #[test]
+        fn mismatched_delimiter_row() {
+            assert_error!(
+                "| a | b |\n|---|",
+                "The table header has 2 columns, but the row under it has 1.",
             )
         }
+    }
+
+    mod roll {
+        use super::*;
 
         #[test]
-        fn multilevel() -> TestResult {
-            assert_html!(
-                r#"> 1
-> 1
-> 1
-> 1
->> 2
->>> 3
->>> 3
->> 2
->> 2
->> 2
-> 1
-> 1
-> 1
-"#,
-                r#"<blockquote>1
-1
-1
-1<blockquote>2<blockquote>3
-3</blockquote>2
-2
-2</blockquote>1
-1
-1</blockquote>"#,
+        fn unclosed_rolls_fast() {
+            assert_fast(&"[[roll ".repeat(10_000));
+        }
+
+        fn expr(count: u32, sides: u32, modifier: i32) -> DiceExpr {
+            DiceExpr {
+                count,
+                sides,
+                modifier,
+            }
+        }
+
+        #[test]
+        fn expressions() -> TestResult {
+            assert_eq!(
+                dice_expressions("[[roll 2d6+3]] and [[roll d20]], then **[[roll 4D8 - 1]]**"),
+                vec![expr(2, 6, 3), expr(1, 20, 0), expr(4, 8, -1)],
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn expression_display() {
+            assert_eq!(expr(2, 6, 3).to_string(), "2d6+3");
+            assert_eq!(expr(1, 20, 0).to_string(), "1d20");
+            assert_eq!(expr(4, 8, -1).to_string(), "4d8-1");
+        }
+
+        #[test]
+        fn rolled() -> TestResult {
+            let roll = DiceRoll {
+                expression: "2d6+3".to_string(),
+                seed: 42,
+                dice: vec![4, 2],
+                total: 9,
+            };
+            assert_html!(
+                "Roll: [[roll 2d6+3]]",
+                "<p>Roll: <span class=\"dice\" data-seed=\"42\">2d6+3: [4, 2] + 3 = <strong>9</strong></span></p>",
+                rolls: &[roll],
             )
         }
 
         #[test]
-        fn reentrant() -> TestResult {
+        fn rolled_negative_modifier() -> TestResult {
+            let roll = DiceRoll {
+                expression: "1d20-2".to_string(),
+                seed: -7,
+                dice: vec![1],
+                total: -1,
+            };
             assert_html!(
-                r#"> 1
-> 1
->> 2
->>> 3
->>> 3
->> 2
->> 2
->>> 3
->> 2
->> 2
-> 1
-> 1
-"#,
-                r#"<blockquote>1
-1<blockquote>2<blockquote>3
-3</blockquote>2
-2<blockquote>3</blockquote>2
-2</blockquote>1
-1</blockquote>"#,
+                "[[roll d20 -2]]",
+                "<p><span class=\"dice\" data-seed=\"-7\">1d20-2: [1] - 2 = <strong>-1</strong></span></p>",
+                rolls: &[roll],
+            )
+        }
+
+        #[test]
+        fn rolls_in_order() -> TestResult {
+            let rolls = [
+                DiceRoll::roll(&expr(1, 6, 0), 1),
+                DiceRoll::roll(&expr(1, 4, 0), 2),
+            ];
+            let html = to_html(
+                "- [[roll 1d6]]\n- [[roll 1d4]]",
+                &Options {
+                    rolls: &rolls,
+                    ..Default::default()
+                },
+            )?
+            .html;
+            assert_eq!(
+                html,
+                format!(
+                    "<ul><li><span class=\"dice\" data-seed=\"1\">1d6: [{}] = <strong>{}</strong></span></li><li><span class=\"dice\" data-seed=\"2\">1d4: [{}] = <strong>{}</strong></span></li></ul>",
+                    rolls[0].dice[0], rolls[0].total, rolls[1].dice[0], rolls[1].total,
+                ),
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn not_rolled() -> TestResult {
+            assert_html!(
+                "[[roll 2d6]]",
+                "<p><span class=\"dice\">2d6: <em>rolled when posted</em></span></p>",
+            )
+        }
+
+        #[test]
+        fn mismatched_roll() -> TestResult {
+            let roll = DiceRoll::roll(&expr(1, 6, 0), 1);
+            assert_html!(
+                "[[roll 2d6]]",
+                "<p><span class=\"dice\">2d6: <em>rolled when posted</em></span></p>",
+                rolls: &[roll],
             )
         }
+
+        #[test]
+        fn not_dice() -> TestResult {
+            assert_html!("[[roll x]] [[roll]]", "<p>[[roll x]] [[roll]]</p>")
+        }
+
+        #[test]
+        fn too_many_dice() {
+            assert_error!("[[roll 101d6]]", "between 1 and 100 dice")
+        }
+
+        #[test]
+        fn too_few_sides() {
+            assert_error!("[[roll 1d1]]", "between 2 and 1000 sides")
+        }
+
+        #[test]
+        fn huge_numbers() {
+            assert_error!("[[roll 99999999999d6]]", "between 1 and 100 dice");
+            assert_error!("[[roll 1d6+99999999999]]", "modifiers can be at most");
+        }
+
+        #[test]
+        fn deterministic() {
+            let expr = expr(10, 20, 5);
+            let roll = DiceRoll::roll(&expr, 1234);
+            assert_eq!(roll, DiceRoll::roll(&expr, 1234));
+            assert_eq!(roll.dice.len(), 10);
+            assert!(roll.dice.iter().all(|die| (1..=20).contains(die)));
+            assert_eq!(roll.total, roll.dice.iter().sum::<i32>() + 5);
+            assert!(roll.verify(&expr));
+        }
+
+        #[test]
+        fn tampered() {
+            let expr = expr(1, 6, 0);
+            let mut roll = DiceRoll::roll(&expr, 99);
+            roll.dice[0] = roll.dice[0] % 6 + 1;
+            roll.total = roll.dice[0];
+            assert!(!roll.verify(&expr));
+        }
+
+        #[test]
+        fn assign_reuses_stored() {
+            let stored = vec![
+                DiceRoll::roll(&expr(1, 20, 0), 1),
+                DiceRoll::roll(&expr(2, 6, 0), 2),
+                DiceRoll::roll(&expr(1, 20, 0), 3),
+            ];
+            let mut seeds = 100..;
+            let (rolls, new_rolls) = dice::assign(
+                &[
+                    expr(2, 6, 0),
+                    expr(1, 20, 0),
+                    expr(1, 20, 0),
+                    expr(1, 20, 0),
+                    expr(1, 4, 0),
+                ],
+                &stored,
+                || seeds.next().unwrap(),
+            );
+            let seeds: Vec<i64> = rolls.iter().map(|roll| roll.seed).collect();
+            assert_eq!(seeds, vec![2, 1, 3, 100, 101]);
+            assert_eq!(new_rolls, rolls[3..].to_vec());
+        }
     }
 
-    mod list {
+    mod link {
         use super::*;
 
         #[test]
-        fn basic_ul() -> TestResult {
+        fn explicit() -> TestResult {
             assert_html!(
-                r#"- hello
-- world
-- how
-- are
-- you
-"#,
-                "<ul><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ul>",
+                "see [the forum](https://example.com/forum) for more",
+                "<p>see <a href=\"https://example.com/forum\" rel=\"nofollow ugc noopener\">the forum</a> for more</p>",
             )
         }
 
         #[test]
-        fn basic_ol() -> TestResult {
+        fn explicit_relative() -> TestResult {
             assert_html!(
-                r#"1. hello
-1. world
-1. how
-1. are
-1. you
-"#,
-                "<ol><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ol>",
+                "[threadmarks](/@qm/quest/threadmarks)",
+                "<p><a href=\"/@qm/quest/threadmarks\" rel=\"nofollow ugc noopener\">threadmarks</a></p>",
             )
         }
 
         #[test]
-        fn basic_ol_numbered() -> TestResult {
+        fn explicit_mailto() -> TestResult {
             assert_html!(
-                r#"1. hello
-2. world
-3. how
-4. are
-5. you
-"#,
-                "<ol><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ol>",
+                "[mail me](mailto:qm@example.com)",
+                "<p><a href=\"mailto:qm@example.com\" rel=\"nofollow ugc noopener\">mail me</a></p>",
             )
         }
 
         #[test]
-        fn formatted_ul() -> TestResult {
+        fn explicit_formatted() -> TestResult {
             assert_html!(
-                r#"- hello //world//
-- **how are you**
-"#,
-                "<ul><li>hello <em>world</em></li><li><strong>how are you</strong></li></ul>",
+                "**[bold](https://example.com)**",
+                "<p><strong><a href=\"https://example.com\" rel=\"nofollow ugc noopener\">bold</a></strong></p>",
             )
         }
 
         #[test]
-        fn formatted_ol() -> TestResult {
+        fn escaped_attributes() -> TestResult {
             assert_html!(
-                r#"1. hello //world//
-1. **how are you**
-"#,
-                "<ol><li>hello <em>world</em></li><li><strong>how are you</strong></li></ol>",
+                "[<b>](https://example.com/?a=1&b=\"2\")",
+                "<p><a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\" rel=\"nofollow ugc noopener\">&lt;b&gt;</a></p>",
             )
         }
 
         #[test]
-        fn single_ul() -> TestResult {
-            assert_html!("- hello world", "<ul><li>hello world</li></ul>")
+        fn unsafe_scheme() -> TestResult {
+            assert_html!(
+                "[click](javascript:alert(1))",
+                "<p>[click](javascript:alert(1))</p>",
+            )
         }
 
         #[test]
-        fn single_ol() -> TestResult {
-            assert_html!("1. hello world", "<ol><li>hello world</li></ol>")
+        fn unsafe_scheme_case() -> TestResult {
+            assert_html!("[x](JavaScript:void)", "<p>[x](JavaScript:void)</p>")
         }
 
         #[test]
-        fn padded_ul() -> TestResult {
+        fn protocol_relative() -> TestResult {
+            assert_html!("[x](//example.com)", "<p>[x](//example.com)</p>")
+        }
+
+        #[test]
+        fn protocol_relative_backslash() -> TestResult {
+            assert_html!("[x](/\\example.com)", "<p>[x](/\\example.com)</p>")
+        }
+
+        #[test]
+        fn not_a_link() -> TestResult {
+            assert_html!("[X] Go north (quickly)", "<p>[X] Go north (quickly)</p>")
+        }
+
+        #[test]
+        fn bracket_in_text() -> TestResult {
+            // A link's text can't contain a "[", so the link starts at the
+            // last one.
             assert_html!(
-                "
+                "[a [b](https://example.com)",
+                "<p>[a <a href=\"https://example.com\" rel=\"nofollow ugc noopener\">b</a></p>",
+            )
+        }
 
-                - hello
-                - world",
-                "<ul><li>hello</li><li>world</li></ul>",
+        #[test]
+        fn escaped_bracket_in_text() -> TestResult {
+            assert_html!(
+                "[a \\[b](https://example.com)",
+                "<p><a href=\"https://example.com\" rel=\"nofollow ugc noopener\">a \\[b</a></p>",
             )
         }
 
         #[test]
-        fn padded_ol() -> TestResult {
+        fn unclosed_brackets_fast() {
+            assert_fast(&"[".repeat(10_000));
+            assert_fast(&"[a](x".repeat(10_000));
+        }
+
+        #[test]
+        fn autolink() -> TestResult {
             assert_html!(
-                "
+                "go to https://example.com/a?b=c now",
+                "<p>go to <a href=\"https://example.com/a?b=c\" rel=\"nofollow ugc noopener\">https://example.com/a?b=c</a> now</p>",
+            )
+        }
 
-                1. hello
-                1. world",
-                "<ol><li>hello</li><li>world</li></ol>",
+        #[test]
+        fn autolink_http() -> TestResult {
+            assert_html!(
+                "http://example.com",
+                "<p><a href=\"http://example.com\" rel=\"nofollow ugc noopener\">http://example.com</a></p>",
             )
         }
 
         #[test]
-        fn nested_ul() -> TestResult {
+        fn autolink_trailing_punctuation() -> TestResult {
             assert_html!(
-                r#"- hello
-- world
-  - how
-  - are
-  - you
-"#,
-                "<ul><li>hello</li><li>world</li><ul><li>how</li><li>are</li><li>you</li></ul></ul>",
+                "(see https://example.com/path.)",
+                "<p>(see <a href=\"https://example.com/path\" rel=\"nofollow ugc noopener\">https://example.com/path</a>.)</p>",
             )
         }
 
         #[test]
-        fn nested_return_ul() -> TestResult {
+        fn autolink_in_italics() -> TestResult {
             assert_html!(
-                r#"- hello
-- world
-  - how
-  - are
-- you
-"#,
-                "<ul><li>hello</li><li>world</li><ul><li>how</li><li>are</li></ul><li>you</li></ul>",
+                "//see https://example.com//",
+                "<p><em>see <a href=\"https://example.com\" rel=\"nofollow ugc noopener\">https://example.com</a></em></p>",
             )
         }
 
         #[test]
-        fn nested_multi_ul() -> TestResult {
+        fn autolink_in_bold() -> TestResult {
             assert_html!(
-                r#"- hello
-  - world
-    - how
-        - are
-            - you
-"#,
-                "<ul><li>hello</li><ul><li>world</li></ul><ul><li>how</li></ul><ul><li>are</li></ul><ul><li>you</li></ul></ul>",
+                "**https://example.com**",
+                "<p><strong><a href=\"https://example.com\" rel=\"nofollow ugc noopener\">https://example.com</a></strong></p>",
+            )
+        }
+
+        #[test]
+        fn autolink_escaped() -> TestResult {
+            assert_html!(
+                "https://example.com/<script>",
+                "<p><a href=\"https://example.com/&lt;script&gt;\" rel=\"nofollow ugc noopener\">https://example.com/&lt;script&gt;</a></p>",
+            )
+        }
+    }
+
+    mod mention {
+        use super::*;
+
+        fn profile(username: &str) -> Mention {
+            Mention::Profile {
+                username: username.to_string(),
+            }
+        }
+
+        fn quest(username: &str, slug: &str) -> Mention {
+            Mention::Quest {
+                username: username.to_string(),
+                slug: slug.to_string(),
+            }
+        }
+
+        #[test]
+        fn profile_basic() -> TestResult {
+            assert_html!(
+                "Thanks, @alice!",
+                "<p>Thanks, <a href=\"/@alice\" class=\"mention\">@alice</a>!</p>",
+            )
+        }
+
+        #[test]
+        fn quest_basic() -> TestResult {
+            assert_html!(
+                "Continued from @alice/dungeon.",
+                "<p>Continued from <a href=\"/@alice/dungeon\" class=\"mention\">@alice/dungeon</a>.</p>",
+            )
+        }
+
+        #[test]
+        fn unknown() -> TestResult {
+            assert_html!("@bob and @alice/castle", "<p>@bob and @alice/castle</p>",)
+        }
+
+        #[test]
+        fn formatted() -> TestResult {
+            assert_html!(
+                "**@alice**",
+                "<p><strong><a href=\"/@alice\" class=\"mention\">@alice</a></strong></p>",
+            )
+        }
+
+        #[test]
+        fn email_address() -> TestResult {
+            assert_html!("mail bob@alice.com", "<p>mail bob@alice.com</p>")
+        }
+
+        #[test]
+        fn not_a_username() -> TestResult {
+            assert_html!(
+                "@Alice @al @alice_ @1alice @alicealicealicealicealice",
+                "<p>@Alice @al @alice_ @1alice @alicealicealicealicealice</p>",
+            )
+        }
+
+        #[test]
+        fn slash_without_slug() -> TestResult {
+            assert_html!(
+                "//ask @alice// or @alice/",
+                "<p><em>ask <a href=\"/@alice\" class=\"mention\">@alice</a></em> or <a href=\"/@alice\" class=\"mention\">@alice</a>/</p>",
+            )
+        }
+
+        #[test]
+        fn in_code_span() -> TestResult {
+            assert_html!("`@alice`", "<p><code>@alice</code></p>")
+        }
+
+        #[test]
+        fn in_link() -> TestResult {
+            assert_html!(
+                "https://example.com/@alice",
+                "<p><a href=\"https://example.com/@alice\" rel=\"nofollow ugc noopener\">https://example.com/@alice</a></p>",
+            )
+        }
+
+        #[test]
+        fn found() -> TestResult {
+            assert_eq!(
+                mentions("@bob met @alice, then @alice/dungeon and @alice again."),
+                [profile("bob"), profile("alice"), quest("alice", "dungeon")],
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn linked() -> TestResult {
+            let known = [profile("alice"), quest("alice", "dungeon")];
+            let rendered = to_html(
+                "@bob met @alice/dungeon, then @alice and @alice again.",
+                &Options {
+                    mentions: &known,
+                    ..Default::default()
+                },
+            )?;
+            assert_eq!(
+                rendered.mentions,
+                [quest("alice", "dungeon"), profile("alice")]
+            );
+            Ok(())
+        }
+    }
+
+    mod image {
+        use super::*;
+
+        #[test]
+        fn unclosed_images_fast() {
+            assert_fast(&"![".repeat(10_000));
+        }
+
+        #[test]
+        fn allowed_host() -> TestResult {
+            assert_html!(
+                "![a dragon](https://images.example.com/dragon.png)",
+                "<p><img src=\"https://images.example.com/dragon.png\" alt=\"a dragon\" loading=\"lazy\" /></p>",
             )
         }
+
+        #[test]
+        fn allowed_host_case() -> TestResult {
+            assert_html!(
+                "![](HTTP://Images.Example.com:8080/a.png)",
+                "<p><img src=\"HTTP://Images.Example.com:8080/a.png\" alt=\"\" loading=\"lazy\" /></p>",
+            )
+        }
+
+        #[test]
+        fn local() -> TestResult {
+            assert_html!(
+                "look: ![map](/uploads/map.png)",
+                "<p>look: <img src=\"/uploads/map.png\" alt=\"map\" loading=\"lazy\" /></p>",
+            )
+        }
+
+        #[test]
+        fn escaped_attributes() -> TestResult {
+            assert_html!(
+                "![\"quoted\" <alt>](/a.png?x=1&y=\")",
+                "<p><img src=\"/a.png?x=1&amp;y=&quot;\" alt=\"&quot;quoted&quot; &lt;alt&gt;\" loading=\"lazy\" /></p>",
+            )
+        }
+
+        #[test]
+        fn in_formatting() -> TestResult {
+            assert_html!(
+                "**![x](/x.png)**",
+                "<p><strong><img src=\"/x.png\" alt=\"x\" loading=\"lazy\" /></strong></p>",
+            )
+        }
+
+        #[test]
+        fn exclamation_before_link() -> TestResult {
+            assert_html!(
+                "wow! [link](/a)",
+                "<p>wow! <a href=\"/a\" rel=\"nofollow ugc noopener\">link</a></p>",
+            )
+        }
+
+        #[test]
+        fn disallowed_host() {
+            assert_error!(
+                "![x](https://evil.example.net/x.png)",
+                "Images must be uploaded to this site or come from one of: images.example.com.",
+            )
+        }
+
+        #[test]
+        fn disallowed_subdomain() {
+            assert_error!(
+                "![x](https://evil.images.example.com/x.png)",
+                "come from one of",
+            )
+        }
+
+        #[test]
+        fn disallowed_credentials() {
+            assert_error!(
+                "![x](https://images.example.com@evil.example.net/x.png)",
+                "come from one of",
+            )
+        }
+
+        #[test]
+        fn disallowed_scheme() {
+            assert_error!("![x](data:image/png;base64,AAAA)", "come from one of")
+        }
+
+        #[test]
+        fn disallowed_protocol_relative() {
+            assert_error!("![x](//evil.example.net/x.png)", "come from one of")
+        }
+
+        #[test]
+        fn disallowed_protocol_relative_backslash() {
+            assert_error!("![x](/\\evil.example.net/x.png)", "come from one of")
+        }
+
+        #[test]
+        fn disallowed_backslash_in_path() {
+            assert_error!("![x](/images\\x.png)", "come from one of")
+        }
+
+        #[test]
+        fn no_hosts_configured() {
+            let err = to_html(
+                "![x](https://images.example.com/x.png)",
+                &Options::default(),
+            )
+            .expect_err("expected an error");
+            assert!(format!("{}", err).contains("Images must be uploaded to this site."));
+        }
+    }
+
+    mod horizontal_rule {
+        use super::*;
+
+        #[test]
+        fn basic() -> TestResult {
+            assert_html!("---", "<hr />")
+        }
+
+        #[test]
+        fn broken() -> TestResult {
+            assert_html!("  --- ---   ", "<hr />")
+        }
+
+        #[test]
+        fn weird() -> TestResult {
+            assert_html!(" - -- ---", "<hr />")
+        }
+    }
+
+    mod paragraph {
+        use super::*;
+
+        #[test]
+        fn multi() -> TestResult {
+            assert_html!(
+                r#"hello
+
+world"#,
+                "<p>hello</p><p>world</p>",
+            )
+        }
+
+        #[test]
+        fn joined() -> TestResult {
+            assert_html!(
+                r#"hello
+world"#,
+                r#"<p>hello
+world</p>"#,
+            )
+        }
+
+        #[test]
+        fn padded() -> TestResult {
+            assert_html!(
+                r#"
+
+hello world
+
+
+"#,
+                "<p>hello world</p>",
+            )
+        }
+    }
+
+    mod blockquote {
+        use super::*;
+
+        #[test]
+        fn basic() -> TestResult {
+            assert_html!("> hello", "<blockquote>hello</blockquote>")
+        }
+
+        #[test]
+        fn formatted() -> TestResult {
+            assert_html!(
+                "> hello //world//",
+                "<blockquote>hello <em>world</em></blockquote>",
+            )
+        }
+
+        #[test]
+        fn continued() -> TestResult {
+            assert_html!(
+                r#"> hello
+> continued"#,
+                r#"<blockquote>hello
+continued</blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn strip_leading() -> TestResult {
+            assert_html!(
+                r#"  > hello
+ > continued"#,
+                r#"<blockquote>hello
+continued</blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn multi_paragraph() -> TestResult {
+            assert_html!(
+                r#"> hello
+>
+> continued"#,
+                r#"<blockquote><p>hello</p><p>continued</p></blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn basic_nesting() -> TestResult {
+            assert_html!(
+                r#"> hello
+> continued
+>> nested"#,
+                r#"<blockquote>hello
+continued<blockquote>nested</blockquote></blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn basic_return() -> TestResult {
+            assert_html!(
+                r#"> hello
+> continued
+>> nested
+> return to original level"#,
+                r#"<blockquote>hello
+continued<blockquote>nested</blockquote>return to original level</blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn nested_many() -> TestResult {
+            assert_html!(
+                ">>>>>>>>>>>>>> hello",
+                "<blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote><blockquote>hello</blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote></blockquote>",
+            )
+        }
+
+        #[test]
+        fn triangular() -> TestResult {
+            assert_html!(
+                r#"> 1
+>> 2
+>>> 3
+>>>> 4
+>>>>> 5
+>>>> 4
+>>> 3
+>> 2
+> 1
+"#,
+                "<blockquote>1<blockquote>2<blockquote>3<blockquote>4<blockquote>5</blockquote>4</blockquote>3</blockquote>2</blockquote>1</blockquote>",
+            )
+        }
+
+        #[test]
+        fn multilevel() -> TestResult {
+            assert_html!(
+                r#"> 1
+> 1
+> 1
+> 1
+>> 2
+>>> 3
+>>> 3
+>> 2
+>> 2
+>> 2
+> 1
+> 1
+> 1
+"#,
+                r#"<blockquote>1
+1
+1
+1<blockquote>2<blockquote>3
+3</blockquote>2
+2
+2</blockquote>1
+1
+1</blockquote>"#,
+            )
+        }
+
+        #[test]
+        fn reentrant() -> TestResult {
+            assert_html!(
+                r#"> 1
+> 1
+>> 2
+>>> 3
+>>> 3
+>> 2
+>> 2
+>>> 3
+>> 2
+>> 2
+> 1
+> 1
+"#,
+                r#"<blockquote>1
+1<blockquote>2<blockquote>3
+3</blockquote>2
+2<blockquote>3</blockquote>2
+2</blockquote>1
+1</blockquote>"#,
+            )
+        }
+    }
+
+    mod list {
+        use super::*;
+
+        #[test]
+        fn basic_ul() -> TestResult {
+            assert_html!(
+                r#"- hello
+- world
+- how
+- are
+- you
+"#,
+                "<ul><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ul>",
+            )
+        }
+
+        #[test]
+        fn basic_ol() -> TestResult {
+            assert_html!(
+                r#"1. hello
+1. world
+1. how
+1. are
+1. you
+"#,
+                "<ol><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ol>",
+            )
+        }
+
+        #[test]
+        fn basic_ol_numbered() -> TestResult {
+            assert_html!(
+                r#"1. hello
+2. world
+3. how
+4. are
+5. you
+"#,
+                "<ol><li>hello</li><li>world</li><li>how</li><li>are</li><li>you</li></ol>",
+            )
+        }
+
+        #[test]
+        fn formatted_ul() -> TestResult {
+            assert_html!(
+                r#"- hello //world//
+- **how are you**
+"#,
+                "<ul><li>hello <em>world</em></li><li><strong>how are you</strong></li></ul>",
+            )
+        }
+
+        #[test]
+        fn formatted_ol() -> TestResult {
+            assert_html!(
+                r#"1. hello //world//
+1. **how are you**
+"#,
+                "<ol><li>hello <em>world</em></li><li><strong>how are you</strong></li></ol>",
+            )
+        }
+
+        #[test]
+        fn single_ul() -> TestResult {
+            assert_html!("- hello world", "<ul><li>hello world</li></ul>")
+        }
+
+        #[test]
+        fn single_ol() -> TestResult {
+            assert_html!("1. hello world", "<ol><li>hello world</li></ol>")
+        }
+
+        #[test]
+        fn padded_ul() -> TestResult {
+            assert_html!(
+                "
+
+                - hello
+                - world",
+                "<ul><li>hello</li><li>world</li></ul>",
+            )
+        }
+
+        #[test]
+        fn padded_ol() -> TestResult {
+            assert_html!(
+                "
+
+                1. hello
+                1. world",
+                "<ol><li>hello</li><li>world</li></ol>",
+            )
+        }
+
+        #[test]
+        fn nested_ul() -> TestResult {
+            assert_html!(
+                r#"- hello
+- world
+  - how
+  - are
+  - you
+"#,
+                "<ul><li>hello</li><li>world</li><ul><li>how</li><li>are</li><li>you</li></ul></ul>",
+            )
+        }
+
+        #[test]
+        fn nested_return_ul() -> TestResult {
+            assert_html!(
+                r#"- hello
+- world
+  - how
+  - are
+- you
+"#,
+                "<ul><li>hello</li><li>world</li><ul><li>how</li><li>are</li></ul><li>you</li></ul>",
+            )
+        }
+
+        #[test]
+        fn nested_multi_ul() -> TestResult {
+            assert_html!(
+                r#"- hello
+  - world
+    - how
+        - are
+            - you
+"#,
+                "<ul><li>hello</li><ul><li>world</li></ul><ul><li>how</li></ul><ul><li>are</li></ul><ul><li>you</li></ul></ul>",
+            )
+        }
+    }
+
+    mod nested {
+        use super::*;
+
+        #[test]
+        fn list_after_paragraph() -> TestResult {
+            assert_html!(
+                "What now?\n- fight\n- flee",
+                "<p>What now?</p><ul><li>fight</li><li>flee</li></ul>",
+            )
+        }
+
+        #[test]
+        fn ordered_list_after_paragraph() -> TestResult {
+            assert_html!(
+                "What now?\n1. fight\n2. flee",
+                "<p>What now?</p><ol><li>fight</li><li>flee</li></ol>",
+            )
+        }
+
+        #[test]
+        fn number_in_paragraph() -> TestResult {
+            assert_html!(
+                "It was the year\n1984. It rained.",
+                "<p>It was the year\n1984. It rained.</p>",
+            )
+        }
+
+        #[test]
+        fn list_in_blockquote() -> TestResult {
+            assert_html!(
+                r#"> The options are:
+> - fight
+> - flee"#,
+                "<blockquote>The options are:<ul><li>fight</li><li>flee</li></ul></blockquote>",
+            )
+        }
+
+        #[test]
+        fn ordered_list_in_blockquote() -> TestResult {
+            assert_html!(
+                "> 1. fight\n> 2. flee",
+                "<blockquote><ol><li>fight</li><li>flee</li></ol></blockquote>",
+            )
+        }
+
+        #[test]
+        fn list_in_nested_blockquote() -> TestResult {
+            assert_html!(
+                "> outer\n>> - a\n>> - b\n> outer again",
+                "<blockquote>outer<blockquote><ul><li>a</li><li>b</li></ul></blockquote>outer again</blockquote>",
+            )
+        }
+
+        #[test]
+        fn blockquote_in_list_item() -> TestResult {
+            assert_html!(
+                r#"- The sign says:
+  > Keep out
+  > //by order of the king//
+- Go in anyway"#,
+                "<ul><li>The sign says:<blockquote>Keep out\n<em>by order of the king</em></blockquote></li><li>Go in anyway</li></ul>",
+            )
+        }
+
+        #[test]
+        fn blockquote_in_ordered_list_item() -> TestResult {
+            assert_html!(
+                "1. a\n   > b\n2. c",
+                "<ol><li>a<blockquote>b</blockquote></li><li>c</li></ol>",
+            )
+        }
+
+        #[test]
+        fn multi_paragraph_list_item() -> TestResult {
+            assert_html!(
+                r#"- First paragraph.
+
+  Second paragraph.
+- Next item"#,
+                "<ul><li><p>First paragraph.</p><p>Second paragraph.</p></li><li>Next item</li></ul>",
+            )
+        }
+
+        #[test]
+        fn multi_paragraph_list_item_continued() -> TestResult {
+            assert_html!("- a\n\n  b\n  c", "<ul><li><p>a</p><p>b\nc</p></li></ul>",)
+        }
+
+        #[test]
+        fn list_item_lazy_continuation() -> TestResult {
+            assert_html!("- hello\nworld", "<ul><li>hello\nworld</li></ul>")
+        }
+
+        #[test]
+        fn list_ends_at_unindented_paragraph() -> TestResult {
+            assert_html!("- a\n\nafter", "<ul><li>a</li></ul><p>after</p>",)
+        }
+
+        #[test]
+        fn blockquote_in_blockquote_in_list_item() -> TestResult {
+            assert_html!(
+                "- a\n  > b\n  >> c",
+                "<ul><li>a<blockquote>b<blockquote>c</blockquote></blockquote></li></ul>",
+            )
+        }
+
+        #[test]
+        fn preformatted_in_blockquote() -> TestResult {
+            assert_html!(
+                "> ```\n> **x**\n> ```\n> after",
+                "<blockquote><pre> **x**</pre>after</blockquote>",
+            )
+        }
+
+        #[test]
+        fn preformatted_in_list_item() -> TestResult {
+            assert_html!(
+                "- map:\n  ```\n  +-+\n  | |\n  ```",
+                "<ul><li>map:<pre>+-+\n| |</pre></li></ul>",
+            )
+        }
+
+        #[test]
+        fn heading_in_blockquote() -> TestResult {
+            assert_html!(
+                "> # Title\n> text",
+                "<blockquote><h2>Title</h2>text</blockquote>",
+            )
+        }
+
+        #[test]
+        fn table_in_blockquote() -> TestResult {
+            assert_html!(
+                "> | a |\n> |---|\n> | b |",
+                "<blockquote><table><thead><tr><th>a</th></tr></thead><tbody><tr><td>b</td></tr></tbody></table></blockquote>",
+            )
+        }
+
+        #[test]
+        fn loose_blockquote_with_list() -> TestResult {
+            assert_html!(
+                "> intro\n>\n> - a\n> - b",
+                "<blockquote><p>intro</p><ul><li>a</li><li>b</li></ul></blockquote>",
+            )
+        }
+
+        #[test]
+        fn formatting_in_nested_list() -> TestResult {
+            assert_html!(
+                "> - **bold //both// bold**",
+                "<blockquote><ul><li><strong>bold <em>both</em> bold</strong></li></ul></blockquote>",
+            )
+        }
+
+        #[test]
+        fn separate_blockquotes() -> TestResult {
+            assert_html!(
+                "> a\n\nbetween\n\n> b",
+                "<blockquote>a</blockquote><p>between</p><blockquote>b</blockquote>",
+            )
+        }
+    }
+
+    mod spoiler_block {
+        use super::*;
+
+        #[test]
+        fn basic() -> TestResult {
+            assert_html!(
+                ":::\nThe butler did it.\n:::",
+                "<details><summary>Spoiler</summary><p>The butler did it.</p></details>",
+            )
+        }
+
+        #[test]
+        fn summary() -> TestResult {
+            assert_html!(
+                "::: The //real// ending\nThe butler did it.\n:::",
+                "<details><summary>The <em>real</em> ending</summary><p>The butler did it.</p></details>",
+            )
+        }
+
+        #[test]
+        fn multiple_blocks() -> TestResult {
+            assert_html!(
+                r#"::: Stats
+
+Strength 12
+Wits 3
+
+- sword
+- lantern
+:::"#,
+                "<details><summary>Stats</summary><p>Strength 12\nWits 3</p><ul><li>sword</li><li>lantern</li></ul></details>",
+            )
+        }
+
+        #[test]
+        fn between_paragraphs() -> TestResult {
+            assert_html!(
+                "Before\n::: Hidden\nInside\n:::\nAfter",
+                "<p>Before</p><details><summary>Hidden</summary><p>Inside</p></details><p>After</p>",
+            )
+        }
+
+        #[test]
+        fn nested() -> TestResult {
+            assert_html!(
+                "::: Outer\n::: Inner\ndeep\n:::\n:::",
+                "<details><summary>Outer</summary><details><summary>Inner</summary><p>deep</p></details></details>",
+            )
+        }
+
+        #[test]
+        fn in_blockquote() -> TestResult {
+            assert_html!(
+                "> :::\n> quoted secret\n> :::",
+                "<blockquote><details><summary>Spoiler</summary><p>quoted secret</p></details></blockquote>",
+            )
+        }
+
+        #[test]
+        fn unclosed_runs_to_end() -> TestResult {
+            assert_html!(
+                "::: Hidden\nInside\n\nStill inside",
+                "<details><summary>Hidden</summary><p>Inside</p><p>Still inside</p></details>",
+            )
+        }
+
+        #[test]
+        fn unclosed_in_blockquote() -> TestResult {
+            assert_html!(
+                "> ::: Hidden\n> Inside\n\nOutside",
+                "<blockquote><details><summary>Hidden</summary><p>Inside</p></details></blockquote><p>Outside</p>",
+            )
+        }
+
+        #[test]
+        fn empty() -> TestResult {
+            assert_html!(":::\n:::", "<p>:::</p><p>:::</p>")
+        }
+
+        #[test]
+        fn fence_needs_space() -> TestResult {
+            assert_html!(
+                ":::Hidden\nInside\n:::",
+                "<p>:::Hidden\nInside</p><p>:::</p>"
+            )
+        }
+    }
+
+    mod tolerant {
+        use super::*;
+
+        fn render(input: &str) -> Rendered {
+            let image_hosts = test_image_hosts();
+            to_html_tolerant(
+                input,
+                &Options {
+                    image_hosts: &image_hosts,
+                    ..Default::default()
+                },
+            )
+        }
+
+        #[test]
+        fn valid() {
+            let rendered = render("**fine**\n\n- still fine");
+            assert_eq!(
+                rendered.html,
+                "<p><strong>fine</strong></p><ul><li>still fine</li></ul>"
+            );
+            assert_eq!(rendered.warnings, []);
+        }
+
+        #[test]
+        fn bad_table() {
+            let rendered = render("Before\n\n| a | b |\n|---|\n| 1 | 2 |\n\nAfter");
+            assert_eq!(
+                rendered.html,
+                "<p>Before</p><p>| a | b |\n|---|\n| 1 | 2 |</p><p>After</p>"
+            );
+            assert_eq!(rendered.warnings.len(), 1);
+            let warning = &rendered.warnings[0];
+            assert_eq!(
+                warning.message,
+                "The table header has 2 columns, but the row under it has 1."
+            );
+            assert_eq!((warning.start, warning.end), ((4, 1), (4, 7)));
+        }
+
+        #[test]
+        fn bad_image() {
+            let rendered = render("one\ntwo ![cat](https://evil.example.com/cat.png) three");
+            assert_eq!(
+                rendered.html,
+                "<p>one\ntwo ![cat](https://evil.example.com/cat.png) three</p>"
+            );
+            assert_eq!(rendered.warnings.len(), 1);
+            assert_eq!(
+                rendered.warnings[0].to_string(),
+                "Line 2, column 5: Images must be uploaded to this site or come from one of: images.example.com."
+            );
+            assert_eq!(rendered.warnings[0].end, (2, 45));
+        }
+
+        #[test]
+        fn escaped() {
+            let rendered = render("![<b>](javascript:alert(1))");
+            assert_eq!(rendered.html, "<p>![&lt;b&gt;](javascript:alert(1))</p>");
+            assert_eq!(rendered.warnings.len(), 1);
+        }
+
+        #[test]
+        fn bad_dice_not_rolled() {
+            let rolls = [DiceRoll::roll(
+                &DiceExpr {
+                    count: 1,
+                    sides: 6,
+                    modifier: 0,
+                },
+                1,
+            )];
+            let rendered = to_html_tolerant(
+                "[[roll 1d1]] [[roll 1d6]]",
+                &Options {
+                    rolls: &rolls,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                rendered.html,
+                format!(
+                    "<p>[[roll 1d1]] <span class=\"dice\" data-seed=\"1\">1d6: [{}] = <strong>{}</strong></span></p>",
+                    rolls[0].dice[0], rolls[0].total
+                )
+            );
+            assert_eq!(rendered.warnings.len(), 1);
+            assert_eq!(
+                dice_expressions("[[roll 1d1]] [[roll 1d6]]"),
+                [DiceExpr {
+                    count: 1,
+                    sides: 6,
+                    modifier: 0
+                }]
+            );
+        }
+
+        #[test]
+        fn marked() {
+            let rendered = to_html_tolerant(
+                "[[roll 0d6]]",
+                &Options {
+                    mark_warnings: true,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                rendered.html,
+                "<p><mark class=\"markup-warning\" title=\"Line 1, column 1: A roll must have between 1 and 100 dice.\">[[roll 0d6]]</mark></p>"
+            );
+        }
+
+        #[test]
+        fn strict_still_fails() {
+            assert_error!("[[roll 0d6]]", "between 1 and 100 dice");
+        }
+    }
+
+    mod ast {
+        use super::*;
+        use crate::markup::ast::{Format, Inline, ListEntry};
+        use proptest::prelude::*;
+
+        #[test]
+        fn structure() -> TestResult {
+            let document = parse("- **bold //both**// and\n  - nested", &Options::default())?;
+            let text = |text: &str| Inline::Text {
+                text: text.to_string(),
+            };
+            let [Block::List(list)] = &document.blocks[..] else {
+                panic!("expected a list: {document:?}");
+            };
+            assert_eq!(
+                list.entries[0],
+                ListEntry::Item {
+                    tight: true,
+                    blocks: vec![Block::Paragraph {
+                        lines: vec![vec![
+                            Inline::Format {
+                                format: Format::Bold,
+                                content: vec![text("bold //both")],
+                            },
+                            text("// and"),
+                        ]],
+                    }],
+                }
+            );
+            assert!(matches!(&list.entries[1], ListEntry::List(nested) if !nested.ordered));
+            Ok(())
+        }
+
+        #[test]
+        fn serialized() -> TestResult {
+            let document = parse("# @alice [[roll 2d6+1]]", &Options::default())?;
+            assert_eq!(
+                serde_json::to_string(&document).unwrap(),
+                r#"{"blocks":[{"type":"heading","level":1,"content":[{"type":"mention","mention":{"type":"profile","username":"alice"}},{"type":"text","text":" "},{"type":"dice","expr":{"count":2,"sides":6,"modifier":1}}]}]}"#
+            );
+            Ok(())
+        }
+
+        /// Pieces of markup which arbitrary documents are made from, to get
+        /// more interesting structure than random characters would.
+        const PIECES: &[&str] = &[
+            "\n",
+            "\n\n",
+            " ",
+            "  ",
+            "text",
+            "**",
+            "//",
+            "__",
+            "~~",
+            "||",
+            "\\",
+            "`code`",
+            "# ",
+            "### ",
+            "> ",
+            ">> ",
+            "- ",
+            "* ",
+            "1. ",
+            "2) ",
+            "---",
+            "```",
+            ":::",
+            "::: Summary",
+            "| a | b |",
+            "|:--|--:|",
+            "|",
+            "[link](https://example.com)",
+            "[bad](javascript:alert)",
+            "https://example.com",
+            "![alt](/image.png)",
+            "![alt](https://elsewhere.com/image.png)",
+            "[[roll 1d6]]",
+            "[[roll 0d6]]",
+            "@alice",
+            "@alice/dungeon",
+            "@bob",
+            "a@alice",
+            "<&\"'>",
+            "\t",
+            "\n  ",
+            "\n   ",
+            "\n  - ",
+            "\n   1. ",
+            "\n>\n> ",
+        ];
+
+        pub(super) fn arbitrary_markup() -> impl Strategy<Value = String> {
+            prop::collection::vec(prop::sample::select(PIECES), 0..40)
+                .prop_map(|pieces| pieces.concat())
+        }
+
+        proptest! {
+            #[test]
+            fn serde_round_trip(markup in arbitrary_markup()) {
+                let mentions = test_mentions();
+                let options = Options {
+                    mentions: &mentions,
+                    ..Default::default()
+                };
+                let document = parse_tolerant(&markup, &options);
+                let json = serde_json::to_string(&document).unwrap();
+                let deserialized: Document = serde_json::from_str(&json).unwrap();
+                prop_assert_eq!(&deserialized, &document);
+                prop_assert_eq!(
+                    deserialized.to_html(&options),
+                    to_html_tolerant(&markup, &options)
+                );
+            }
+
+            #[test]
+            fn strict_matches_tolerant(markup in arbitrary_markup()) {
+                let options = Options::default();
+                if let Ok(rendered) = to_html(&markup, &options) {
+                    prop_assert!(rendered.warnings.is_empty());
+                    prop_assert_eq!(rendered, to_html_tolerant(&markup, &options));
+                }
+            }
+        }
+    }
+
+    mod text {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn text(markup: &str) -> String {
+            to_text(markup, &Options::default())
+        }
+
+        #[test]
+        fn formatting_stripped() {
+            assert_eq!(
+                text("# Title\n\n**Bold** and //italic// with `code`, [a link](/wiki) and ![a map](/map.png)."),
+                "Title Bold and italic with code, a link and a map."
+            );
+        }
+
+        #[test]
+        fn whitespace_collapsed() {
+            assert_eq!(
+                text("one\n  two\n\n> three\n\n- four\n  - five\n\n---\n\n| six | seven |\n|---|---|\n| eight | |"),
+                "one two three four five six seven eight"
+            );
+        }
+
+        #[test]
+        fn spoilers_hidden() {
+            assert_eq!(
+                text("The ||butler|| did it.\n\n::: Who?\nThe butler.\n:::\n\n:::\nThe gardener.\n:::"),
+                "The [spoiler] did it. Who? [spoiler] [spoiler]"
+            );
+        }
+
+        #[test]
+        fn spoiler_summary_formatted() {
+            assert_eq!(
+                text("::: **Who** did ||it||?\nThe butler."),
+                "Who did [spoiler]? [spoiler]"
+            );
+        }
+
+        #[test]
+        fn mentions_and_unrendered() {
+            assert_eq!(
+                text("@alice @bob/quest ![x](https://elsewhere.com/x.png)"),
+                "@alice @bob/quest ![x](https://elsewhere.com/x.png)"
+            );
+        }
+
+        #[test]
+        fn dice() {
+            let roll = |expression: &str, total| DiceRoll {
+                expression: expression.to_string(),
+                seed: 1,
+                dice: vec![total],
+                total,
+            };
+            let rolls = [
+                roll("1d6", 1),
+                roll("1d8", 2),
+                roll("1d10", 3),
+                roll("1d12", 4),
+            ];
+            let options = Options {
+                rolls: &rolls,
+                ..Default::default()
+            };
+            // Dice in spoilers are hidden, but still use up their rolls.
+            assert_eq!(
+                to_text("[[roll 1d6]] ||[[roll 1d8]]|| [[roll 1d10]]\n\n:::\n[[roll 1d12]]\n:::\n\n[[roll 1d20]]", &options),
+                "1d6: 1 [spoiler] 1d10: 3 [spoiler] 1d20"
+            );
+        }
+
+        #[test]
+        fn excerpt_short() {
+            assert_eq!(
+                to_excerpt("Short **post**.", &Options::default(), 20),
+                "Short post."
+            );
+        }
+
+        #[test]
+        fn excerpt_word_boundary() {
+            let options = Options::default();
+            assert_eq!(
+                to_excerpt("The party enters the dungeon.", &options, 16),
+                "The party…"
+            );
+            assert_eq!(
+                to_excerpt("The party enters the dungeon.", &options, 10),
+                "The party…"
+            );
+            assert_eq!(
+                to_excerpt("The party enters the dungeon.", &options, 9),
+                "The…"
+            );
+        }
+
+        #[test]
+        fn excerpt_long_word() {
+            assert_eq!(
+                to_excerpt("Aaaaaaaaaargh!", &Options::default(), 6),
+                "Aaaaa…"
+            );
+        }
+
+        #[test]
+        fn excerpt_multibyte() {
+            assert_eq!(
+                to_excerpt("héllo wörld ünïcode", &Options::default(), 12),
+                "héllo wörld…"
+            );
+        }
+
+        proptest! {
+            #[test]
+            fn excerpt_fits(markup in ".{0,300}", max_chars in 1usize..100) {
+                let excerpt = to_excerpt(&markup, &Options::default(), max_chars);
+                prop_assert!(excerpt.chars().count() <= max_chars);
+            }
+        }
+    }
+
+    mod tidy {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Remove whitespace at the ends of lines and before tags, which
+        /// tidying trims.
+        fn trimmed_html(markup: &str) -> String {
+            let html = to_html_tolerant(markup, &Options::default()).html;
+            let mut trimmed = String::new();
+            for c in html.chars() {
+                if matches!(c, '\n' | '<') {
+                    trimmed.truncate(trimmed.trim_end_matches([' ', '\t']).len());
+                }
+                trimmed.push(c);
+            }
+            trimmed.trim_end_matches([' ', '\t']).to_string()
+        }
+
+        #[test]
+        fn lists() {
+            assert_eq!(
+                tidy("- One\n-   Two\n\n5. Three\n9. Four\n10. Five"),
+                "- One\n- Two\n\n1. Three\n2. Four\n3. Five"
+            );
+        }
+
+        #[test]
+        fn nested_lists() {
+            assert_eq!(
+                tidy("- One\n    - Two\n    - Three\n- Four"),
+                "- One\n  - Two\n  - Three\n- Four"
+            );
+        }
+
+        #[test]
+        fn list_items() {
+            // Continuation lines are lined up with the text after the marker,
+            // and blocks in loose items stay apart.
+            assert_eq!(
+                tidy("10. One\n two\n\n  > Quote\n11. Three"),
+                "1. One\n   two\n\n   > Quote\n2. Three"
+            );
+            assert_eq!(tidy("- One\n  > Quote"), "- One\n  > Quote");
+        }
+
+        #[test]
+        fn blockquotes() {
+            assert_eq!(
+                tidy(">Quote\n>   continued\n>\n>>Nested\n>> > More"),
+                "> Quote\n> continued\n>\n>> Nested\n>>> More"
+            );
+        }
+
+        #[test]
+        fn horizontal_rules() {
+            assert_eq!(tidy("Above\n\n-----\n\nBelow"), "Above\n\n---\n\nBelow");
+        }
+
+        #[test]
+        fn whitespace() {
+            assert_eq!(
+                tidy("\n\nOne  \ntwo\t\n\n\n\n# Heading   \n\n"),
+                "One\ntwo\n\n# Heading"
+            );
+            // An escaped space would become an escaped newline if it were
+            // trimmed.
+            assert_eq!(tidy("One\\ \ntwo"), "One\\ \ntwo");
+        }
+
+        #[test]
+        fn preformatted() {
+            assert_eq!(
+                tidy(">```\n>  indented  \n>```"),
+                "> ```\n>  indented  \n> ```"
+            );
+        }
+
+        #[test]
+        fn tables() {
+            assert_eq!(
+                tidy("|a|b|c|\n|:-|:-:|-:|\n|1|2|3|"),
+                "| a | b | c |\n| :--- | :---: | ---: |\n| 1 | 2 | 3 |"
+            );
+        }
+
+        #[test]
+        fn spoilers() {
+            assert_eq!(
+                tidy("::: Summary  \nHidden\n\n\n- item\n:::"),
+                "::: Summary\nHidden\n\n- item\n:::"
+            );
+        }
+
+        #[test]
+        fn inline_text_kept() {
+            let markup = "**Bold**  //italic// [[roll d6+1]] @alice [link](https://example.com)";
+            assert_eq!(
+                tidy(markup),
+                "**Bold**  //italic// [[roll 1d6+1]] @alice [link](https://example.com)"
+            );
+        }
+
+        proptest! {
+            #[test]
+            fn renders_the_same(markup in ast::arbitrary_markup()) {
+                prop_assert_eq!(trimmed_html(&tidy(&markup)), trimmed_html(&markup));
+            }
+
+            #[test]
+            fn idempotent(markup in ast::arbitrary_markup()) {
+                let tidied = tidy(&markup);
+                prop_assert_eq!(tidy(&tidied), tidied);
+            }
+        }
     }
 
     mod document {
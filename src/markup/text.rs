@@ -0,0 +1,148 @@
+//! Rendering a [`Document`] as plain text, for places that can't show HTML,
+//! like feeds, notifications and link previews.
+
+use super::ast::{self, Block, Document, Format, Inline, List, ListEntry};
+use super::dice::DiceRoll;
+use super::Options;
+
+/// Shown in place of spoilers, so that none of their text leaks.
+const SPOILER_PLACEHOLDER: &str = "[spoiler]";
+
+/// Render a document as a single line of text, without formatting. Spoilers
+/// are replaced by a placeholder, though the summaries of spoiler blocks are
+/// kept, since they're shown while the block is collapsed.
+pub(super) fn render(document: &Document, options: &Options) -> String {
+    let mut writer = Writer {
+        rolls: options.rolls.iter(),
+        text: String::new(),
+    };
+    writer.blocks(&document.blocks);
+    writer.text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Shorten text to at most `max_chars` characters, breaking between words
+/// and marking the cut with an ellipsis.
+pub(super) fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    // Leave room for the ellipsis.
+    let end = text
+        .char_indices()
+        .nth(max_chars.saturating_sub(1))
+        .map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+    // Break at the last space unless the next word starts right after the
+    // cut, in which case the cut is already between words.
+    let cut = match (text[end..].starts_with(' '), cut.rfind(' ')) {
+        (false, Some(space)) => &cut[..space],
+        _ => cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+struct Writer<'a> {
+    rolls: std::slice::Iter<'a, DiceRoll>,
+    text: String,
+}
+
+impl Writer<'_> {
+    /// Write blocks, separating them so that words from neighbouring blocks
+    /// aren't joined.
+    fn blocks(&mut self, blocks: &[Block]) {
+        for block in blocks {
+            self.block(block);
+            self.text.push('\n');
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        match block {
+            Block::Paragraph { lines } => {
+                for line in lines {
+                    self.inlines(line);
+                    self.text.push('\n');
+                }
+            }
+            Block::Heading { content, .. } => self.inlines(content),
+            Block::Preformatted { text } => self.text.push_str(text),
+            Block::Table(table) => {
+                for row in std::iter::once(&table.header).chain(&table.rows) {
+                    for cell in row {
+                        self.inlines(cell);
+                        self.text.push(' ');
+                    }
+                    self.text.push('\n');
+                }
+            }
+            Block::HorizontalRule => (),
+            Block::Blockquote { blocks, .. } => self.blocks(blocks),
+            Block::List(list) => self.list(list),
+            Block::Spoiler { summary, blocks } => {
+                if let Some(summary) = summary {
+                    self.inlines(summary);
+                    self.text.push(' ');
+                }
+                self.text.push_str(SPOILER_PLACEHOLDER);
+                // Dice in the spoiler still use up their rolls.
+                for block in blocks {
+                    ast::block_inlines(block, &mut |inline| self.skip_roll(inline));
+                }
+            }
+            Block::Unrendered { text, .. } => self.text.push_str(text),
+        }
+    }
+
+    fn list(&mut self, list: &List) {
+        for entry in &list.entries {
+            match entry {
+                ListEntry::Item { blocks, .. } => self.blocks(blocks),
+                ListEntry::List(list) => self.list(list),
+            }
+        }
+    }
+
+    fn inlines(&mut self, content: &[Inline]) {
+        for inline in content {
+            self.inline(inline);
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline) {
+        match inline {
+            Inline::Text { text } | Inline::Code { code: text } => self.text.push_str(text),
+            Inline::Format {
+                format: Format::Spoiler,
+                content,
+            } => {
+                self.text.push_str(SPOILER_PLACEHOLDER);
+                ast::inlines(content, &mut |inline| self.skip_roll(inline));
+            }
+            Inline::Format { content, .. } => self.inlines(content),
+            Inline::Link { text, .. } => self.text.push_str(text),
+            Inline::Autolink { url } => self.text.push_str(url),
+            Inline::Image { alt, .. } => self.text.push_str(alt),
+            Inline::Dice { expr } => {
+                let expression = expr.to_string();
+                match self
+                    .rolls
+                    .next()
+                    .filter(|roll| roll.expression == expression)
+                {
+                    Some(roll) => self.text.push_str(&format!("{expression}: {}", roll.total)),
+                    None => self.text.push_str(&expression),
+                }
+            }
+            Inline::Mention { mention } => self.text.push_str(&mention.to_string()),
+            Inline::Unrendered { text, .. } => self.text.push_str(text),
+        }
+    }
+
+    /// Skip the roll for a die in hidden content, so that later dice get the
+    /// right ones.
+    fn skip_roll(&mut self, inline: &Inline) {
+        if let Inline::Dice { .. } = inline {
+            self.rolls.next();
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Writing a [`Document`] back as markup, in a canonical form.
+//!
+//! Blocks are separated by one blank line, except in tight containers, where
+//! a blank line would change how they render. Lists use `-` and are numbered
+//! from 1, the contents of list items are indented to line up with the text
+//! after the marker, nested blockquotes are written as `>>` and horizontal
+//! rules as `---`. Text inside a line is written as it was.
+
+use super::ast::{Alignment, Block, Document, Inline, List, ListEntry};
+use super::{parse_tolerant, Options};
+
+/// Tidy markup, trimming whitespace from the ends of lines and writing it in
+/// canonical form.
+///
+/// A few odd documents can't be written in canonical form without changing
+/// them, like a paragraph of `:::` which would become a spoiler once the lines
+/// after it are indented consistently, since escaping it would show the
+/// backslash. Those are left as they are, so tidying never changes how
+/// markup renders, and tidying twice gives the same markup as tidying once.
+pub(super) fn tidy(markup: &str) -> String {
+    let options = Options::default();
+    let mut document = parse_tolerant(markup, &options);
+    trim_document(&mut document);
+    let tidied = write(&document);
+
+    let mut reparsed = parse_tolerant(&tidied, &options);
+    let renders_the_same = reparsed.to_html(&options).html == document.to_html(&options).html;
+    trim_document(&mut reparsed);
+    if renders_the_same && write(&reparsed) == tidied {
+        tidied
+    } else {
+        markup.to_string()
+    }
+}
+
+/// Trim whitespace from the ends of the lines in a document. Paragraphs,
+/// headings and spoiler summaries can end in whitespace, while table cells
+/// are trimmed when they're parsed.
+fn trim_document(document: &mut Document) {
+    trim_blocks(&mut document.blocks, false);
+}
+
+/// Trim the lines of blocks. Paragraphs in tight containers are run together,
+/// so whitespace at the end of one followed by another is kept.
+fn trim_blocks(blocks: &mut [Block], tight: bool) {
+    for i in 0..blocks.len() {
+        let run_together = tight && matches!(blocks.get(i + 1), Some(Block::Paragraph { .. }));
+        match &mut blocks[i] {
+            Block::Paragraph { lines } => {
+                let keep_last = usize::from(run_together);
+                let end = lines.len().saturating_sub(keep_last);
+                lines[..end].iter_mut().for_each(trim_line);
+            }
+            Block::Heading { content, .. } => trim_line(content),
+            Block::Blockquote { tight, blocks } => trim_blocks(blocks, *tight),
+            Block::List(list) => trim_list(list),
+            Block::Spoiler { summary, blocks } => {
+                if let Some(summary) = summary {
+                    trim_line(summary);
+                }
+                trim_blocks(blocks, false);
+            }
+            Block::Preformatted { .. }
+            | Block::Table(_)
+            | Block::HorizontalRule
+            | Block::Unrendered { .. } => (),
+        }
+    }
+}
+
+fn trim_list(list: &mut List) {
+    for entry in &mut list.entries {
+        match entry {
+            ListEntry::Item { tight, blocks } => trim_blocks(blocks, *tight),
+            ListEntry::List(list) => trim_list(list),
+        }
+    }
+}
+
+/// Trim whitespace from the end of a line, unless it's escaped by a
+/// backslash, which would then escape the newline instead. A space is kept
+/// after a lone "-" or "1.", which would otherwise continue the paragraph
+/// before it.
+fn trim_line(line: &mut Vec<Inline>) {
+    let lone = line.len() == 1;
+    let Some(Inline::Text { text }) = line.last_mut() else {
+        return;
+    };
+    let trimmed = text.trim_end_matches([' ', '\t']).len();
+    if trimmed == text.len() {
+        return;
+    }
+    let backslashes = text[..trimmed]
+        .chars()
+        .rev()
+        .take_while(|&c| c == '\\')
+        .count();
+    let keep_space = backslashes % 2 == 1 || (lone && ["-", "1."].contains(&&text[..trimmed]));
+    text.truncate(if keep_space { trimmed + 1 } else { trimmed });
+    if text.is_empty() {
+        line.pop();
+    }
+}
+
+/// Write a document as markup.
+pub(super) fn write(document: &Document) -> String {
+    let mut writer = Writer {
+        lines: Vec::new(),
+        closes: Vec::new(),
+    };
+    writer.blocks(&document.blocks, "", false);
+    // Spoilers which are still open at the end of the document run to the
+    // end, so their closing fences can be left out, which is necessary if the
+    // line before them can't be followed by a newline.
+    let mut end = writer.lines.len();
+    while end > 0 && writer.closes.contains(&(end - 1)) {
+        end -= 1;
+    }
+    if end > 0 && !can_end_line(&writer.lines[end - 1]) {
+        writer.lines.truncate(end);
+    }
+    // Likewise, there's no newline at the end of the document.
+    writer.lines.join("\n")
+}
+
+/// Whether a line can be followed by a newline without changing its meaning.
+/// A backslash at the end would escape the newline, and a line of only `>` and
+/// whitespace would become a blank line.
+fn can_end_line(line: &str) -> bool {
+    let backslashes = line.chars().rev().take_while(|&c| c == '\\').count();
+    backslashes % 2 == 0 && !line.chars().all(|c| matches!(c, ' ' | '\t' | '>'))
+}
+
+/// What separates a container's prefix from the content of a line. Content
+/// after a blockquote's `>` is set apart by a space, which isn't part of the
+/// prefix, so that nested blockquotes can still be written as `>>`.
+fn separator(prefix: &str) -> &'static str {
+    if prefix.ends_with('>') {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// Marker for a list item, given its position in the list.
+fn list_marker(ordered: bool, position: usize) -> String {
+    if ordered {
+        format!("{}. ", position + 1)
+    } else {
+        "- ".to_string()
+    }
+}
+
+fn table_delimiter(alignment: Alignment) -> &'static str {
+    match alignment {
+        Alignment::Default => "---",
+        Alignment::Left => ":---",
+        Alignment::Center => ":---:",
+        Alignment::Right => "---:",
+    }
+}
+
+/// Split text on any of the newlines the grammar accepts.
+fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['\r', '\n']) {
+        lines.push(&rest[..i]);
+        let newline = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+        rest = &rest[i + newline..];
+    }
+    lines.push(rest);
+    lines
+}
+
+struct Writer {
+    lines: Vec<String>,
+    /// Indices of the lines which close spoilers.
+    closes: Vec<usize>,
+}
+
+impl Writer {
+    /// Write a line of content after a prefix.
+    fn line(&mut self, prefix: &str, content: &str) {
+        self.lines
+            .push(format!("{prefix}{}{content}", separator(prefix)));
+    }
+
+    /// Write a blank line inside the containers that `prefix` belongs to.
+    fn blank(&mut self, prefix: &str) {
+        self.lines.push(prefix.trim_end().to_string());
+    }
+
+    /// Write blocks whose lines start with `prefix`, separating them with
+    /// blank lines unless they're in a tight container.
+    fn blocks(&mut self, blocks: &[Block], prefix: &str, tight: bool) {
+        for (i, block) in blocks.iter().enumerate() {
+            if i > 0 && !tight {
+                self.blank(prefix);
+            }
+            self.block(block, prefix);
+        }
+    }
+
+    fn block(&mut self, block: &Block, prefix: &str) {
+        match block {
+            Block::Paragraph { lines } => {
+                for line in lines {
+                    self.line(prefix, &inlines(line));
+                }
+            }
+            Block::Heading { level, content } => {
+                let content = format!("{} {}", "#".repeat(*level), inlines(content));
+                self.line(prefix, &content);
+            }
+            Block::Preformatted { text } => {
+                self.line(prefix, "```");
+                // Preformatted lines are kept exactly, including any
+                // whitespace after the prefix.
+                for line in split_lines(text) {
+                    self.lines.push(format!("{prefix}{line}"));
+                }
+                self.line(prefix, "```");
+            }
+            Block::Table(table) => {
+                let row = |cells: &[Vec<Inline>]| {
+                    let cells: Vec<String> = cells.iter().map(|cell| inlines(cell)).collect();
+                    format!("| {} |", cells.join(" | "))
+                };
+                self.line(prefix, &row(&table.header));
+                let delimiter: Vec<&str> = table
+                    .alignments
+                    .iter()
+                    .map(|&alignment| table_delimiter(alignment))
+                    .collect();
+                self.line(prefix, &format!("| {} |", delimiter.join(" | ")));
+                for cells in &table.rows {
+                    self.line(prefix, &row(cells));
+                }
+            }
+            Block::HorizontalRule => self.line(prefix, "---"),
+            Block::Blockquote { tight, blocks } => {
+                let prefix = format!("{prefix}>");
+                if blocks.is_empty() {
+                    self.blank(&prefix);
+                }
+                self.blocks(blocks, &prefix, *tight);
+            }
+            Block::List(list) => {
+                // Lists in blockquotes are set apart from the `>`, and the
+                // space becomes part of their prefix.
+                let prefix = format!("{prefix}{}", separator(prefix));
+                self.list(list, &prefix);
+            }
+            Block::Spoiler { summary, blocks } => {
+                match summary {
+                    Some(summary) => self.line(prefix, &format!("::: {}", inlines(summary))),
+                    None => self.line(prefix, ":::"),
+                }
+                self.blocks(blocks, prefix, false);
+                self.closes.push(self.lines.len());
+                self.line(prefix, ":::");
+            }
+            Block::Unrendered { text, .. } => {
+                // Only tables are left unrendered as blocks, and any lines
+                // after the first keep the prefixes they were written with,
+                // which are replaced.
+                for (i, line) in split_lines(text).into_iter().enumerate() {
+                    let line = if i == 0 {
+                        line.trim_start_matches([' ', '\t'])
+                    } else {
+                        line.trim_start_matches([' ', '\t', '>'])
+                    };
+                    self.line(prefix, line);
+                }
+            }
+        }
+    }
+
+    /// Write a list whose markers start right after `prefix`.
+    fn list(&mut self, list: &List, prefix: &str) {
+        // Indentation of nested lists, which line up with the contents of the
+        // item before them. If that item has more blocks after its paragraph,
+        // they're indented one space less, so as not to continue them.
+        let mut nested_indent = String::new();
+        let mut position = 0;
+        for entry in &list.entries {
+            match entry {
+                ListEntry::Item { tight, blocks } => {
+                    let marker = list_marker(list.ordered, position);
+                    position += 1;
+                    let indent = " ".repeat(marker.len());
+                    let inner = format!("{prefix}{indent}");
+                    nested_indent = if blocks.len() > 1 {
+                        " ".repeat(marker.len() - 1)
+                    } else {
+                        indent
+                    };
+                    let mut blocks = blocks.as_slice();
+                    match blocks.first() {
+                        Some(Block::Paragraph { lines }) if !lines.is_empty() => {
+                            self.line(prefix, &format!("{marker}{}", inlines(&lines[0])));
+                            for line in &lines[1..] {
+                                self.line(&inner, &inlines(line));
+                            }
+                            blocks = &blocks[1..];
+                        }
+                        _ => self.line(prefix, marker.trim_end()),
+                    }
+                    for block in blocks {
+                        if !tight {
+                            self.blank(&inner);
+                        }
+                        self.block(block, &inner);
+                    }
+                }
+                ListEntry::List(nested) => self.list(nested, &format!("{prefix}{nested_indent}")),
+            }
+        }
+    }
+}
+
+/// Write the content of a line.
+fn inlines(content: &[Inline]) -> String {
+    let mut markup = String::new();
+    for inline in content {
+        inline_markup(&mut markup, inline);
+    }
+    markup
+}
+
+fn inline_markup(markup: &mut String, inline: &Inline) {
+    match inline {
+        Inline::Text { text }
+        | Inline::Autolink { url: text }
+        | Inline::Unrendered { text, .. } => markup.push_str(text),
+        Inline::Format { format, content } => {
+            markup.push_str(format.control());
+            for inline in content {
+                inline_markup(markup, inline);
+            }
+            markup.push_str(format.control());
+        }
+        Inline::Code { code } => {
+            markup.push('`');
+            markup.push_str(code);
+            markup.push('`');
+        }
+        Inline::Link { text, url } => markup.push_str(&format!("[{text}]({url})")),
+        Inline::Image { alt, url } => markup.push_str(&format!("![{alt}]({url})")),
+        Inline::Dice { expr } => markup.push_str(&format!("[[roll {expr}]]")),
+        Inline::Mention { mention } => markup.push_str(&mention.to_string()),
+    }
+}
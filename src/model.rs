@@ -0,0 +1,230 @@
+/// Rust counterparts of database types that are shared between routes.
+use serde::Deserialize;
+
+/// What kind of comment a quest comment is. Mirrors `quest_comment_type`.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "quest_comment_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum CommentType {
+    /// Just a comment.
+    Comment,
+    /// A command intended for the QM to execute next turn.
+    Command,
+    /// A question for the QM.
+    Question,
+}
+
+impl CommentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommentType::Comment => "comment",
+            CommentType::Command => "command",
+            CommentType::Question => "question",
+        }
+    }
+}
+
+/// Lifecycle state of a quest post. Mirrors `quest_post_state`.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "quest_post_state", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PostState {
+    /// Still a draft, visible only to the QM.
+    Draft,
+    /// Published and accepting new player commands.
+    AcceptCommand,
+    /// No longer accepting commands, but accepting votes on them.
+    AcceptVote,
+    /// No longer accepting commands or votes.
+    Finalized,
+}
+
+impl PostState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostState::Draft => "draft",
+            PostState::AcceptCommand => "accept_command",
+            PostState::AcceptVote => "accept_vote",
+            PostState::Finalized => "finalized",
+        }
+    }
+
+    /// Human readable description of the state.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PostState::Draft => "Draft",
+            PostState::AcceptCommand => "Accepting commands",
+            PostState::AcceptVote => "Voting",
+            PostState::Finalized => "Finalized",
+        }
+    }
+
+    /// States that a post in this state may move to. Posts only ever move
+    /// forward, so e.g. commands can't be reopened once voting has started.
+    pub fn transitions(&self) -> &'static [PostState] {
+        match self {
+            PostState::Draft => &[PostState::AcceptCommand, PostState::Finalized],
+            PostState::AcceptCommand => &[PostState::AcceptVote, PostState::Finalized],
+            PostState::AcceptVote => &[PostState::Finalized],
+            PostState::Finalized => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: PostState) -> bool {
+        self.transitions().contains(&next)
+    }
+
+    /// Label for the action that moves a post from this state to `next`.
+    pub fn action_label(&self, next: &PostState) -> &'static str {
+        match (self, next) {
+            (PostState::Draft, PostState::AcceptCommand) => "Publish and open commands",
+            (PostState::Draft, PostState::Finalized) => "Publish without commands",
+            (PostState::AcceptCommand, PostState::AcceptVote) => "Close commands and open voting",
+            (PostState::AcceptCommand, PostState::Finalized) => "Close commands",
+            (PostState::AcceptVote, PostState::Finalized) => "Close voting and finalize",
+            _ => "Change state",
+        }
+    }
+
+    pub fn is_published(&self) -> bool {
+        *self != PostState::Draft
+    }
+
+    pub fn accepts_commands(&self) -> bool {
+        *self == PostState::AcceptCommand
+    }
+
+    pub fn accepts_votes(&self) -> bool {
+        *self == PostState::AcceptVote
+    }
+}
+
+/// How votes on the commands under a post are counted. Mirrors
+/// `quest_vote_mode`.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "quest_vote_mode", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum VoteMode {
+    /// Each voter picks at most one command.
+    Single,
+    /// Each voter may vote for any number of commands.
+    Approval,
+}
+
+impl VoteMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            VoteMode::Single => "Single choice",
+            VoteMode::Approval => "Approval",
+        }
+    }
+
+    /// Instructions shown to voters.
+    pub fn instructions(&self) -> &'static str {
+        match self {
+            VoteMode::Single => "Vote for the one command you want the most.",
+            VoteMode::Approval => "Vote for every command you would be happy with.",
+        }
+    }
+}
+
+/// Whether a quest is active or not, according to its QM. Mirrors
+/// `quest_publish_state`.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "quest_publish_state", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum QuestPublishState {
+    /// Quest is still a draft/idea.
+    Prepping,
+    /// Quest is active.
+    Active,
+    /// Quest is on hiatus.
+    Hiatus,
+    /// Quest is cancelled.
+    Cancelled,
+    /// Quest is complete.
+    Complete,
+}
+
+impl QuestPublishState {
+    pub const ALL: [QuestPublishState; 5] = [
+        QuestPublishState::Prepping,
+        QuestPublishState::Active,
+        QuestPublishState::Hiatus,
+        QuestPublishState::Cancelled,
+        QuestPublishState::Complete,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuestPublishState::Prepping => "prepping",
+            QuestPublishState::Active => "active",
+            QuestPublishState::Hiatus => "hiatus",
+            QuestPublishState::Cancelled => "cancelled",
+            QuestPublishState::Complete => "complete",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            QuestPublishState::Prepping => "Prepping",
+            QuestPublishState::Active => "Active",
+            QuestPublishState::Hiatus => "On hiatus",
+            QuestPublishState::Cancelled => "Cancelled",
+            QuestPublishState::Complete => "Complete",
+        }
+    }
+
+    /// Tailwind classes for the state's badge on the quest page.
+    pub fn badge_class(&self) -> &'static str {
+        match self {
+            QuestPublishState::Prepping => "bg-slate-200 text-slate-800",
+            QuestPublishState::Active => "bg-green-200 text-green-900",
+            QuestPublishState::Hiatus => "bg-yellow-200 text-yellow-900",
+            QuestPublishState::Cancelled => "bg-red-200 text-red-900",
+            QuestPublishState::Complete => "bg-blue-200 text-blue-900",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATES: [PostState; 4] = [
+        PostState::Draft,
+        PostState::AcceptCommand,
+        PostState::AcceptVote,
+        PostState::Finalized,
+    ];
+
+    #[test]
+    fn forward_transitions() {
+        assert!(PostState::Draft.can_transition_to(PostState::AcceptCommand));
+        assert!(PostState::Draft.can_transition_to(PostState::Finalized));
+        assert!(PostState::AcceptCommand.can_transition_to(PostState::AcceptVote));
+        assert!(PostState::AcceptCommand.can_transition_to(PostState::Finalized));
+        assert!(PostState::AcceptVote.can_transition_to(PostState::Finalized));
+    }
+
+    #[test]
+    fn no_backward_or_self_transitions() {
+        for (i, from) in ALL_STATES.iter().enumerate() {
+            for to in ALL_STATES[..=i].iter() {
+                assert!(!from.can_transition_to(*to), "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn no_voting_straight_from_draft() {
+        assert!(!PostState::Draft.can_transition_to(PostState::AcceptVote));
+    }
+
+    #[test]
+    fn commands_only_while_accepting() {
+        for state in ALL_STATES {
+            assert_eq!(state.accepts_commands(), state == PostState::AcceptCommand);
+        }
+    }
+}
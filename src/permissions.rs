@@ -1,7 +1,19 @@
 /// Code related to verifying user permissions.
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use uuid::Uuid;
 
-/// Mutually exclusive roles related to moderation.
-enum AdminRole {
+use crate::app_state::{AppState, SessionInfo};
+use crate::error::{Error, Result};
+
+/// Mutually exclusive roles related to moderation. Ordered by privilege, so
+/// comparisons like `role >= AdminRole::SiteModerator` work.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[sqlx(type_name = "admin_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
     /// Unprivileged user. May or may not be a questmaster.
     User,
     /// Site-level moderator. Capable of making moderation decisions on any
@@ -12,10 +24,521 @@ enum AdminRole {
     Administrator,
 }
 
-/// Mutually exclusive roles related to
-enum TechRole {
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::User => "user",
+            AdminRole::SiteModerator => "site_moderator",
+            AdminRole::Administrator => "administrator",
+        }
+    }
+
+    /// Human readable name of the role.
+    pub fn label(&self) -> &'static str {
+        match self {
+            AdminRole::User => "User",
+            AdminRole::SiteModerator => "Site moderator",
+            AdminRole::Administrator => "Administrator",
+        }
+    }
+}
+
+/// Mutually exclusive roles related to running the site itself. Ordered by
+/// privilege.
+#[derive(sqlx::Type, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[sqlx(type_name = "tech_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TechRole {
     /// Unprivileged user.
     User,
     /// Technical administrator/site operator.
     Operator,
 }
+
+/// Checks that an account with the role `actual` has at least the role
+/// `required`.
+pub fn check_admin_role(actual: AdminRole, required: AdminRole) -> Result<()> {
+    if actual < required {
+        return Err(Error::ForbiddenError(format!(
+            "This page is only available to the {} role and above.",
+            required.label().to_lowercase()
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that an account with the role `actual` has at least the role
+/// `required`.
+pub fn check_tech_role(actual: TechRole, required: TechRole) -> Result<()> {
+    if actual < required {
+        return Err(Error::ForbiddenError(
+            "This page is only available to site operators.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Access settings of a quest, along with whether the current account is on
+/// its allowlist. Used to decide who may view and comment on a quest.
+#[derive(sqlx::FromRow, Debug)]
+pub struct QuestAccess {
+    pub quest_id: Uuid,
+    pub questmaster: Uuid,
+    pub general_access: bool,
+    pub general_commenting: bool,
+    pub require_log_in_to_view: bool,
+    pub allowlisted: bool,
+}
+
+impl QuestAccess {
+    /// Loads the access settings of the quest `slug` run by `username`.
+    pub async fn load(
+        db_pool: &sqlx::postgres::PgPool,
+        username: &str,
+        slug: &str,
+        account_id: Option<Uuid>,
+    ) -> Result<QuestAccess> {
+        let access: Option<QuestAccess> = sqlx::query_as(
+            r#"
+            select
+              quest.id as quest_id,
+              questmaster,
+              general_access,
+              general_commenting,
+              require_log_in_to_view,
+              exists(
+                select 1
+                from quest_allowed_user
+                  join profile allowed on allowed.id = quest_allowed_user.profile_id
+                where quest_allowed_user.quest_id = quest.id
+                  and allowed.account_id = $3
+                limit 1
+              ) as allowlisted
+            from quest
+              join profile on questmaster = profile.account_id
+            where profile.username = $1
+              and quest.slug = $2
+            "#,
+        )
+        .bind(username)
+        .bind(slug)
+        .bind(account_id)
+        .fetch_optional(db_pool)
+        .await
+        .context("Failed to fetch quest permissions")?;
+        if let Some(access) = access {
+            return Ok(access);
+        }
+
+        // Tell apart a mistyped username from a mistyped quest.
+        let (user_exists,): (bool,) = sqlx::query_as(
+            r#"
+            select exists(
+              select 1
+              from profile
+              where username = $1
+              limit 1
+            )
+            "#,
+        )
+        .bind(username)
+        .fetch_one(db_pool)
+        .await
+        .context("Failed to check if user exists")?;
+        Err(Error::NotFoundError(if user_exists {
+            format!("@{username} has no quest \"{slug}\".")
+        } else {
+            format!("The user @{username} doesn't exist.")
+        }))
+    }
+
+    pub fn is_questmaster(&self, account_id: Option<Uuid>) -> bool {
+        account_id == Some(self.questmaster)
+    }
+
+    /// Checks that the account (or a guest, if `None`) may view the quest.
+    pub fn check_view(&self, account_id: Option<Uuid>) -> Result<()> {
+        if self.is_questmaster(account_id) {
+            return Ok(());
+        }
+        if account_id.is_none() && (self.require_log_in_to_view || !self.general_access) {
+            return Err(Error::AuthorizationError(
+                "You must be logged in to view this quest.".to_string(),
+            ));
+        }
+        if !self.general_access && !self.allowlisted {
+            return Err(Error::ForbiddenError(
+                "This quest is only viewable by invited readers.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the account may comment, post commands and vote on the
+    /// quest. Implies being able to view it.
+    pub fn check_comment(&self, account_id: Option<Uuid>) -> Result<()> {
+        self.check_view(account_id)?;
+        if self.is_questmaster(account_id) {
+            return Ok(());
+        }
+        if account_id.is_none() {
+            return Err(Error::AuthorizationError(
+                "You must be logged in to comment.".to_string(),
+            ));
+        }
+        if !self.general_commenting && !self.allowlisted {
+            return Err(Error::ForbiddenError(
+                "Only invited players can comment on this quest.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn can_comment(&self, account_id: Option<Uuid>) -> bool {
+        self.check_comment(account_id).is_ok()
+    }
+}
+
+/// Checks that `account_id` owns something owned by `owner`, e.g. a quest or a
+/// profile. `what` names the thing for the error message.
+pub fn check_owner(owner: Uuid, account_id: Uuid, what: &str) -> Result<()> {
+    if owner != account_id {
+        return Err(Error::AuthorizationError(format!("You don't own {what}.")));
+    }
+    Ok(())
+}
+
+/// Checks that the profile `username` belongs to the account and returns its
+/// ID. Profiles are looked up by username in forms, so this has to be done
+/// before acting on one.
+pub async fn require_own_profile(
+    db_pool: &sqlx::postgres::PgPool,
+    account_id: Uuid,
+    username: &str,
+) -> Result<Uuid> {
+    let (profile_id, owner): (Uuid, Uuid) = sqlx::query_as(
+        r#"
+        select id, account_id
+        from profile
+        where username = $1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch profile owner")?
+    .ok_or_else(|| Error::NotFoundError(format!("The profile @{username} doesn't exist.")))?;
+    check_owner(owner, account_id, &format!("the profile @{username}"))?;
+    Ok(profile_id)
+}
+
+/// A logged in account with a profile selected. Quests are addressed by their
+/// QM's username, so running quests requires one.
+pub struct Questmaster(pub SessionInfo);
+
+impl FromRequest for Questmaster {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        Box::pin(async move {
+            let app_state = request
+                .app_data::<web::Data<AppState>>()
+                .context("Missing app state")?
+                .clone();
+            let session = app_state.require_session(request).await?;
+            if session.current_profile.is_none() {
+                return Err(Error::AuthorizationError(
+                    "Choose a profile to run quests.".to_string(),
+                ));
+            }
+            Ok(Questmaster(session))
+        })
+    }
+}
+
+/// A quest run by the logged in account, identified by the `{slug}` segment of
+/// the route. Extracting this rejects requests from anyone but the QM.
+pub struct OwnedQuest {
+    pub id: Uuid,
+    pub title: String,
+    pub slug: String,
+    pub session: SessionInfo,
+}
+
+impl OwnedQuest {
+    /// Checks that a post with the given quest and QM belongs to this quest.
+    fn check_post_owner(&self, quest_id: Uuid, questmaster: Uuid) -> Result<()> {
+        check_owner(questmaster, self.session.account_id, "that quest post")?;
+        if quest_id != self.id {
+            return Err(Error::NotFoundError(format!(
+                "That post isn't part of the quest \"{}\".",
+                self.slug
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the quest found for `slug`, with its ID, title and QM,
+    /// belongs to the session's account.
+    fn check(
+        quest: Option<(Uuid, String, Uuid)>,
+        slug: String,
+        session: SessionInfo,
+    ) -> Result<OwnedQuest> {
+        let (id, title, questmaster) = quest
+            .ok_or_else(|| Error::NotFoundError(format!("The quest \"{slug}\" doesn't exist.")))?;
+        check_owner(questmaster, session.account_id, "that quest")?;
+        Ok(OwnedQuest {
+            id,
+            title,
+            slug,
+            session,
+        })
+    }
+
+    /// Checks that the post exists and belongs to this quest. Post IDs come
+    /// from the request, so they can't be trusted to match the slug.
+    pub async fn check_post(&self, db_pool: &sqlx::postgres::PgPool, post_id: Uuid) -> Result<()> {
+        let (quest_id, questmaster): (Uuid, Uuid) = sqlx::query_as(
+            r#"
+            select quest.id, questmaster
+            from quest_post
+              join quest on quest.id = quest_post.quest
+            where quest_post.id = $1
+            "#,
+        )
+        .bind(post_id)
+        .fetch_optional(db_pool)
+        .await
+        .context("Failed to fetch post owner")?
+        .ok_or_else(|| Error::NotFoundError("That quest post doesn't exist.".to_string()))?;
+        self.check_post_owner(quest_id, questmaster)
+    }
+}
+
+impl FromRequest for OwnedQuest {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        Box::pin(async move {
+            let app_state = request
+                .app_data::<web::Data<AppState>>()
+                .context("Missing app state")?
+                .clone();
+            let slug = request
+                .match_info()
+                .get("slug")
+                .context("Route has no quest slug")?
+                .to_string();
+            let session = app_state.require_session(request).await?;
+
+            let quest: Option<(Uuid, String, Uuid)> = sqlx::query_as(
+                r#"
+                select id, title, questmaster
+                from quest
+                where slug = $1
+                "#,
+            )
+            .bind(&slug)
+            .fetch_optional(&app_state.db_pool)
+            .await
+            .context("Failed to fetch quest")?;
+
+            OwnedQuest::check(quest, slug, session)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn access() -> QuestAccess {
+        QuestAccess {
+            quest_id: Uuid::nil(),
+            questmaster: Uuid::from_u128(1),
+            general_access: true,
+            general_commenting: true,
+            require_log_in_to_view: false,
+            allowlisted: false,
+        }
+    }
+
+    const QM: Option<Uuid> = Some(Uuid::from_u128(1));
+    const READER: Option<Uuid> = Some(Uuid::from_u128(2));
+    const GUEST: Option<Uuid> = None;
+
+    #[test]
+    fn public_quest() {
+        let access = access();
+        assert!(access.check_view(GUEST).is_ok());
+        assert!(access.check_view(READER).is_ok());
+        assert!(matches!(
+            access.check_comment(GUEST),
+            Err(Error::AuthorizationError(_))
+        ));
+        assert!(access.check_comment(READER).is_ok());
+    }
+
+    #[test]
+    fn log_in_to_view() {
+        let access = QuestAccess {
+            require_log_in_to_view: true,
+            ..access()
+        };
+        assert!(matches!(
+            access.check_view(GUEST),
+            Err(Error::AuthorizationError(_))
+        ));
+        assert!(access.check_view(READER).is_ok());
+    }
+
+    #[test]
+    fn private_quest() {
+        let access = QuestAccess {
+            general_access: false,
+            ..access()
+        };
+        assert!(matches!(
+            access.check_view(GUEST),
+            Err(Error::AuthorizationError(_))
+        ));
+        assert!(matches!(
+            access.check_view(READER),
+            Err(Error::ForbiddenError(_))
+        ));
+        assert!(matches!(
+            access.check_comment(READER),
+            Err(Error::ForbiddenError(_))
+        ));
+        assert!(access.check_view(QM).is_ok());
+        assert!(access.check_comment(QM).is_ok());
+
+        let allowlisted = QuestAccess {
+            allowlisted: true,
+            ..access
+        };
+        assert!(allowlisted.check_view(READER).is_ok());
+        assert!(allowlisted.check_comment(READER).is_ok());
+    }
+
+    #[test]
+    fn closed_commenting() {
+        let access = QuestAccess {
+            general_commenting: false,
+            ..access()
+        };
+        assert!(access.check_view(READER).is_ok());
+        assert!(matches!(
+            access.check_comment(READER),
+            Err(Error::ForbiddenError(_))
+        ));
+        assert!(access.check_comment(QM).is_ok());
+
+        let allowlisted = QuestAccess {
+            allowlisted: true,
+            ..access
+        };
+        assert!(allowlisted.check_comment(READER).is_ok());
+    }
+
+    fn session(account_id: Uuid) -> SessionInfo {
+        SessionInfo {
+            raw: HashMap::new(),
+            account_id,
+            session_id: String::new(),
+            current_profile: None,
+            admin_role: AdminRole::User,
+            tech_role: TechRole::User,
+        }
+    }
+
+    fn owned_quest(account_id: Uuid) -> OwnedQuest {
+        OwnedQuest {
+            id: Uuid::from_u128(10),
+            title: "Quest".to_string(),
+            slug: "quest".to_string(),
+            session: session(account_id),
+        }
+    }
+
+    #[test]
+    fn own_quest() {
+        let quest = Some((Uuid::from_u128(10), "Quest".to_string(), QM.unwrap()));
+        let owned = OwnedQuest::check(quest, "quest".to_string(), session(QM.unwrap()));
+        assert!(owned.is_ok_and(|owned| owned.id == Uuid::from_u128(10)));
+    }
+
+    #[test]
+    fn quest_of_another_account() {
+        let quest = Some((Uuid::from_u128(10), "Quest".to_string(), QM.unwrap()));
+        assert!(matches!(
+            OwnedQuest::check(quest, "quest".to_string(), session(READER.unwrap())),
+            Err(Error::AuthorizationError(_))
+        ));
+    }
+
+    #[test]
+    fn missing_quest() {
+        assert!(matches!(
+            OwnedQuest::check(None, "quest".to_string(), session(QM.unwrap())),
+            Err(Error::NotFoundError(_))
+        ));
+    }
+
+    #[test]
+    fn owner() {
+        let qm = QM.unwrap();
+        let reader = READER.unwrap();
+        assert!(check_owner(qm, qm, "the quest").is_ok());
+        assert!(matches!(
+            check_owner(qm, reader, "the quest"),
+            Err(Error::AuthorizationError(_))
+        ));
+    }
+
+    #[test]
+    fn post_of_another_account() {
+        let quest = owned_quest(READER.unwrap());
+        // Someone else's post, addressed through the reader's own quest.
+        assert!(matches!(
+            quest.check_post_owner(Uuid::from_u128(11), QM.unwrap()),
+            Err(Error::AuthorizationError(_))
+        ));
+    }
+
+    #[test]
+    fn post_of_another_quest() {
+        let quest = owned_quest(QM.unwrap());
+        assert!(quest.check_post_owner(quest.id, QM.unwrap()).is_ok());
+        assert!(matches!(
+            quest.check_post_owner(Uuid::from_u128(11), QM.unwrap()),
+            Err(Error::NotFoundError(_))
+        ));
+    }
+
+    #[test]
+    fn roles() {
+        assert!(check_admin_role(AdminRole::Administrator, AdminRole::SiteModerator).is_ok());
+        assert!(check_admin_role(AdminRole::SiteModerator, AdminRole::SiteModerator).is_ok());
+        assert!(matches!(
+            check_admin_role(AdminRole::User, AdminRole::SiteModerator),
+            Err(Error::ForbiddenError(_))
+        ));
+        assert!(matches!(
+            check_admin_role(AdminRole::SiteModerator, AdminRole::Administrator),
+            Err(Error::ForbiddenError(_))
+        ));
+        assert!(check_tech_role(TechRole::Operator, TechRole::Operator).is_ok());
+        assert!(matches!(
+            check_tech_role(TechRole::User, TechRole::Operator),
+            Err(Error::ForbiddenError(_))
+        ));
+    }
+}
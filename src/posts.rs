@@ -0,0 +1,142 @@
+/// Rendering quest posts and keeping track of their dice rolls.
+use anyhow::Context;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::app_state::{AppConfig, AppState};
+use crate::error::Result;
+use crate::markup::{self, dice, dice::DiceRoll, mention::Mention, Warning};
+
+/// Longest excerpt kept for a post, in characters.
+const EXCERPT_LENGTH: usize = 280;
+
+/// A post's markup converted to HTML, with its dice rolled.
+pub struct RenderedPost {
+    pub html: String,
+    /// Plain-text summary for feeds and link previews.
+    pub excerpt: String,
+    /// Rolls made for this render, which must be stored with the post.
+    pub new_rolls: Vec<DiceRoll>,
+    /// Markup which couldn't be rendered, and is shown as text instead.
+    pub warnings: Vec<Warning>,
+}
+
+/// Fetches the rolls made for a post so far, oldest first.
+pub async fn stored_rolls(db_pool: &sqlx::PgPool, post_id: Uuid) -> Result<Vec<DiceRoll>> {
+    let rows: Vec<(String, i64, Vec<i32>, i32)> = sqlx::query_as(
+        r#"
+        select expression, seed, dice, total
+        from quest_post_roll
+        where quest_post = $1
+        order by id
+        "#,
+    )
+    .bind(post_id)
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch dice rolls")?;
+    Ok(rows
+        .into_iter()
+        .map(|(expression, seed, dice, total)| DiceRoll {
+            expression,
+            seed,
+            dice,
+            total,
+        })
+        .collect())
+}
+
+/// Renders a post, reusing the `stored` rolls for its dice where possible and
+/// rolling the rest. Only the known `mentions` are linked. Posts are never
+/// rejected for their markup, so anything which can't be rendered is shown as
+/// text.
+pub fn render_post(
+    config: &AppConfig,
+    body_markup: &str,
+    stored: &[DiceRoll],
+    mentions: &[Mention],
+) -> RenderedPost {
+    let expressions = markup::dice_expressions(body_markup);
+    let (rolls, new_rolls) = dice::assign(&expressions, stored, rand::random);
+    let options = markup::Options {
+        rolls: &rolls,
+        mentions,
+        ..config.markup_options()
+    };
+    let document = markup::parse_tolerant(body_markup, &options);
+    let rendered = document.to_html(&options);
+    RenderedPost {
+        html: rendered.html,
+        excerpt: document.to_excerpt(&options, EXCERPT_LENGTH),
+        new_rolls,
+        warnings: rendered.warnings,
+    }
+}
+
+/// Fills in the excerpts of posts saved before excerpts were stored with
+/// them, which are null until then. Run once at startup, and a no-op after
+/// that.
+pub async fn backfill_excerpts(app_state: &AppState) -> Result<()> {
+    let posts: Vec<(Uuid, String)> = sqlx::query_as(
+        r#"
+        select id, body_markup
+        from quest_post
+        where excerpt is null
+        "#,
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .context("Failed to fetch posts without excerpts")?;
+
+    for (post_id, body_markup) in posts {
+        let stored = stored_rolls(&app_state.db_pool, post_id).await?;
+        // Every die in a saved post has a stored roll, so nothing new is
+        // rolled.
+        let expressions = markup::dice_expressions(&body_markup);
+        let (rolls, _) = dice::assign(&expressions, &stored, rand::random);
+        let options = markup::Options {
+            rolls: &rolls,
+            ..app_state.config.markup_options()
+        };
+        sqlx::query(
+            r#"
+            update quest_post
+            set excerpt = $1
+            where id = $2 and excerpt is null
+            "#,
+        )
+        .bind(markup::to_excerpt(&body_markup, &options, EXCERPT_LENGTH))
+        .bind(post_id)
+        .execute(&app_state.db_pool)
+        .await
+        .context("Failed to save post excerpt")?;
+    }
+    Ok(())
+}
+
+/// Saves rolls made for a post.
+pub async fn store_rolls(
+    connection: &mut PgConnection,
+    app_state: &AppState,
+    post_id: Uuid,
+    rolls: &[DiceRoll],
+) -> Result<()> {
+    for roll in rolls {
+        sqlx::query(
+            r#"
+            insert into quest_post_roll (id, quest_post, expression, seed, dice, total)
+            values ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(Uuid::now_v6(&app_state.uuid_seed))
+        .bind(post_id)
+        .bind(&roll.expression)
+        .bind(roll.seed)
+        .bind(&roll.dice)
+        .bind(roll.total)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to save dice roll")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::permissions::AdminRole;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(view_roles).service(change_role)
+}
+
+/// Output object for the staff list query.
+#[derive(sqlx::FromRow, Debug)]
+struct StaffMember {
+    admin_role: AdminRole,
+    /// All profile usernames of the account, comma separated.
+    usernames: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/roles.html")]
+struct RolesTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    staff: &'a Vec<StaffMember>,
+    messages: &'a Vec<String>,
+}
+
+/// Lists site staff, with a form for appointing moderators.
+#[get("/roles")]
+async fn view_roles(
+    app_state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let session_info = app_state
+        .require_admin_role(request, AdminRole::Administrator)
+        .await?;
+    view_roles_fn(&app_state, session_info, &Vec::new()).await
+}
+
+async fn view_roles_fn(
+    app_state: &AppState,
+    session_info: SessionInfo,
+    messages: &Vec<String>,
+) -> Result<HttpResponse> {
+    let staff: Vec<StaffMember> = sqlx::query_as(
+        r#"
+        select
+          admin_role,
+          string_agg(profile.username, ', ' order by profile.username) as usernames
+        from account
+          left join profile on profile.account_id = account.id
+        where admin_role <> 'user'::admin_role
+        group by account.id
+        order by admin_role desc, account.created_at asc
+        "#,
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .context("Failed to fetch staff")?;
+
+    Ok(RolesTemplate {
+        config: &app_state.config,
+        logged_in: true,
+        current_profile: &session_info.current_profile,
+        staff: &staff,
+        messages,
+    }
+    .to_response())
+}
+
+#[derive(Deserialize)]
+struct RoleForm {
+    /// Any profile username of the account to change.
+    username: String,
+    role: AdminRole,
+}
+
+/// Appoints or removes a site moderator. Administrators themselves can only be
+/// appointed or removed by site operators, directly in the database.
+#[post("/roles")]
+async fn change_role(
+    app_state: web::Data<AppState>,
+    form: web::Form<RoleForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let session_info = app_state
+        .require_admin_role(request, AdminRole::Administrator)
+        .await?;
+    let RoleForm { username, role } = form.into_inner();
+    validation::username(&username)?;
+
+    if role == AdminRole::Administrator {
+        return Err(Error::AppError(
+            "Administrators can only be appointed by site operators.".to_string(),
+        ));
+    }
+
+    let (account_id, current_role): (Uuid, AdminRole) = sqlx::query_as(
+        r#"
+        select account.id, admin_role
+        from profile
+          join account on profile.account_id = account.id
+        where profile.username = $1
+        "#,
+    )
+    .bind(&username)
+    .fetch_optional(&app_state.db_pool)
+    .await
+    .context("Failed to fetch account role")?
+    .ok_or_else(|| Error::NotFoundError(format!("The profile @{username} doesn't exist.")))?;
+
+    if current_role == AdminRole::Administrator {
+        return Err(Error::AppError(format!(
+            "@{username} is an administrator, which can only be changed by site operators."
+        )));
+    }
+
+    sqlx::query(
+        r#"
+        update account
+        set admin_role = $1
+        where id = $2
+        "#,
+    )
+    .bind(role)
+    .bind(account_id)
+    .execute(&app_state.db_pool)
+    .await
+    .context("Failed to set account role")?;
+
+    info!(
+        "Account {} set role of @{} to {}",
+        session_info.account_id,
+        username,
+        role.as_str()
+    );
+    let messages = vec![format!(
+        "Changed role of @{} from {} to {}",
+        username,
+        current_role.label().to_lowercase(),
+        role.label().to_lowercase()
+    )];
+    view_roles_fn(&app_state, session_info, &messages).await
+}
@@ -1,31 +1,123 @@
 use actix_web::dev::ServiceFactory;
 use actix_web::dev::ServiceRequest;
+use actix_web::http::header::ContentType;
 
-use crate::markup;
+use crate::markup::{self, mention::Mention, Warning};
 use crate::routes::prelude::*;
 
+/// Largest markup which may be previewed or tidied, in bytes. Posts are saved
+/// through forms with the same limit, so anything longer couldn't be posted.
+const MAX_MARKUP_SIZE: usize = 16 * 1024;
+
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
-    scope.service(preview)
+    scope
+        .app_data(web::FormConfig::default().limit(MAX_MARKUP_SIZE))
+        .service(preview)
+        .service(tidy)
+}
+
+/// Looks up which of the profiles and quests mentioned in some markup exist,
+/// so that they can be linked.
+pub(crate) async fn known_mentions(
+    db_pool: &sqlx::PgPool,
+    body_markup: &str,
+) -> Result<Vec<Mention>> {
+    let mentions = markup::mentions(body_markup);
+    if mentions.is_empty() {
+        return Ok(mentions);
+    }
+
+    let usernames: Vec<&str> = mentions.iter().map(Mention::username).collect();
+    let (quest_usernames, slugs): (Vec<&str>, Vec<&str>) = mentions
+        .iter()
+        .filter_map(|mention| match mention {
+            Mention::Quest { username, slug } => Some((username.as_str(), slug.as_str())),
+            Mention::Profile { .. } => None,
+        })
+        .unzip();
+    let profiles = sqlx::query_scalar(
+        r#"
+        select username::text
+        from profile
+        where username = any($1)
+        "#,
+    )
+    .bind(&usernames)
+    .fetch_all(db_pool);
+    let quests = sqlx::query_as(
+        r#"
+        select profile.username::text, quest.slug::text
+        from quest
+          join profile on profile.account_id = quest.questmaster
+          join unnest($1::text[], $2::text[]) as wanted (username, slug)
+            on profile.username = wanted.username and quest.slug = wanted.slug
+        "#,
+    )
+    .bind(&quest_usernames)
+    .bind(&slugs)
+    .fetch_all(db_pool);
+    let (profiles, quests): (Vec<String>, Vec<(String, String)>) =
+        try_join!(profiles, quests).context("Failed to look up mentions")?;
+
+    Ok(mentions
+        .into_iter()
+        .filter(|mention| match mention {
+            Mention::Profile { username } => profiles.contains(username),
+            Mention::Quest { username, slug } => {
+                quests.iter().any(|(quest_username, quest_slug)| {
+                    quest_username == username && quest_slug == slug
+                })
+            }
+        })
+        .collect())
 }
 
 #[derive(Template)]
-#[template(source = "<pre>{{ pre_text }}</pre>", ext = "html")]
-struct PreTemplate<'a> {
-    pre_text: &'a str,
+#[template(path = "markup/preview.html")]
+struct PreviewTemplate<'a> {
+    html: &'a str,
+    warnings: &'a [Warning],
 }
 
 #[derive(Debug, Deserialize)]
-struct PreviewForm {
+struct MarkupForm {
     body: String,
 }
 
+/// Renders markup the way it would be posted, highlighting anything which
+/// can't be rendered.
 #[post("/preview")]
-pub async fn preview(form: web::Form<PreviewForm>) -> impl Responder {
-    match markup::to_html(form.body.as_str()) {
-        Ok(html) => html,
-        Err(err) => PreTemplate {
-            pre_text: format!("{}", err).as_str(),
-        }
-        .to_string(),
+pub async fn preview(
+    app_state: web::Data<AppState>,
+    form: web::Form<MarkupForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    app_state.require_session(request).await?;
+    let mentions = known_mentions(&app_state.db_pool, &form.body).await?;
+    let rendered = markup::to_html_tolerant(
+        &form.body,
+        &markup::Options {
+            mentions: &mentions,
+            mark_warnings: true,
+            ..app_state.config.markup_options()
+        },
+    );
+    Ok(PreviewTemplate {
+        html: &rendered.html,
+        warnings: &rendered.warnings,
     }
+    .to_response())
+}
+
+/// Rewrites markup in canonical form, to replace what's in the editor.
+#[post("/tidy")]
+pub async fn tidy(
+    app_state: web::Data<AppState>,
+    form: web::Form<MarkupForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    app_state.require_session(request).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(markup::tidy(&form.body)))
 }
@@ -1,3 +1,4 @@
+mod admin;
 mod auth;
 mod home;
 mod markup;
@@ -14,7 +15,8 @@ pub fn add_routes<T>(app: actix_web::App<T>) -> actix_web::App<T>
 where
     T: ServiceFactory<ServiceRequest, Config = (), Error = actix_web::Error, InitError = ()>,
 {
-    app.service(auth::add_routes(web::scope("/auth")))
+    app.service(admin::add_routes(web::scope("/admin")))
+        .service(auth::add_routes(web::scope("/auth")))
         .service(markup::add_routes(web::scope("/markup")))
         .service(qm::add_routes(web::scope("/qm")))
         .service(quest::add_routes(web::scope("/@{username}")))
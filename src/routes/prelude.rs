@@ -9,6 +9,6 @@ pub use tokio::{join, try_join};
 pub use uuid::Uuid;
 
 pub use crate::app_state::{AppConfig, AppState, ProfileRenderInfo, SessionInfo};
-pub use crate::error::{Error, Result};
+pub use crate::error::{DbContext, Error, Result};
 pub use crate::partials::*;
 pub use crate::validation;
@@ -0,0 +1,161 @@
+use crate::permissions::OwnedQuest;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope
+        .service(create_chapter)
+        .service(delete_chapter)
+        .service(set_post_chapter)
+}
+
+/// Maximum length of a chapter title, in characters.
+const MAX_CHAPTER_TITLE_LENGTH: usize = 250;
+
+#[derive(Deserialize)]
+struct ChapterForm {
+    title: String,
+}
+
+#[post("/edit/{slug}/chapters")]
+async fn create_chapter(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    form: web::Form<ChapterForm>,
+) -> Result<impl Responder> {
+    let title = form.title.trim();
+    if title.chars().count() > MAX_CHAPTER_TITLE_LENGTH {
+        return Err(Error::AppError(format!(
+            "Chapter titles can be at most {MAX_CHAPTER_TITLE_LENGTH} characters long."
+        )));
+    }
+
+    sqlx::query(
+        r#"
+        insert into quest_chapter (id, quest, title)
+        values ($1, $2, $3)
+        "#,
+    )
+    .bind(Uuid::now_v6(&app_state.uuid_seed))
+    .bind(quest.id)
+    .bind(title)
+    .execute(&app_state.db_pool)
+    .await
+    .db_context("Failed to create chapter")?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}", quest.slug)).see_other())
+}
+
+/// Deletes a chapter. Its posts are kept, just without a chapter.
+#[post("/edit/{slug}/chapters/{chapter_id}/delete")]
+async fn delete_chapter(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+) -> Result<impl Responder> {
+    let (_, chapter_id) = info.into_inner();
+
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+
+    sqlx::query(
+        r#"
+        update quest_post
+        set chapter = null
+        where chapter = $1 and quest = $2
+        "#,
+    )
+    .bind(chapter_id)
+    .bind(quest.id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove posts from chapter")?;
+
+    if sqlx::query(
+        r#"
+        delete from quest_chapter
+        where id = $1 and quest = $2
+        "#,
+    )
+    .bind(chapter_id)
+    .bind(quest.id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete chapter")?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::NotFoundError(
+            "That chapter doesn't exist.".to_string(),
+        ));
+    }
+
+    transaction.commit().await.context("Failed to commit")?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}", quest.slug)).see_other())
+}
+
+#[derive(Deserialize)]
+struct PostChapterForm {
+    /// Chapter ID, or empty for no chapter.
+    chapter: String,
+}
+
+#[post("/edit/{slug}/post/{post_id}/chapter")]
+async fn set_post_chapter(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+    form: web::Form<PostChapterForm>,
+) -> Result<impl Responder> {
+    let (_, post_id) = info.into_inner();
+    quest.check_post(&app_state.db_pool, post_id).await?;
+
+    let chapter_id = match form.chapter.as_str() {
+        "" => None,
+        chapter => Some(
+            Uuid::try_parse(chapter)
+                .map_err(|_| Error::AppError("Invalid chapter.".to_string()))?,
+        ),
+    };
+
+    if let Some(chapter_id) = chapter_id {
+        let (exists,): (bool,) = sqlx::query_as(
+            r#"
+            select exists(
+              select 1
+              from quest_chapter
+              where id = $1 and quest = $2
+              limit 1
+            )
+            "#,
+        )
+        .bind(chapter_id)
+        .bind(quest.id)
+        .fetch_one(&app_state.db_pool)
+        .await
+        .context("Failed to check chapter")?;
+        if !exists {
+            return Err(Error::NotFoundError(
+                "That chapter doesn't exist.".to_string(),
+            ));
+        }
+    }
+
+    sqlx::query(
+        r#"
+        update quest_post
+        set chapter = $1
+        where id = $2
+        "#,
+    )
+    .bind(chapter_id)
+    .bind(post_id)
+    .execute(&app_state.db_pool)
+    .await
+    .context("Failed to set post chapter")?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}", quest.slug)).see_other())
+}
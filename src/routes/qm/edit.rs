@@ -1,10 +1,42 @@
+use chrono::{DateTime, Utc};
+
+use crate::model::PostState;
+use crate::partials;
+use crate::permissions::OwnedQuest;
+use crate::posts;
+use crate::routes::markup::known_mentions;
 use crate::routes::prelude::*;
-use crate::{markup, partials};
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
     scope.service(edit_quest).service(edit_quest_submit)
 }
 
+/// Output object for the QM's list of posts.
+#[derive(sqlx::FromRow, Debug)]
+struct QmPost {
+    id: Uuid,
+    title: Option<String>,
+    state: PostState,
+    created_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+    winning_command: Option<String>,
+    chapter: Option<Uuid>,
+}
+
+impl QmPost {
+    fn in_chapter(&self, chapter: &Uuid) -> bool {
+        self.chapter.as_ref() == Some(chapter)
+    }
+}
+
+/// Output object for the QM's list of chapters.
+#[derive(sqlx::FromRow, Debug)]
+struct QmChapter {
+    id: Uuid,
+    title: String,
+    post_count: i64,
+}
+
 #[derive(Template)]
 #[template(path = "qm/edit.html")]
 struct EditQuestTemplate<'a> {
@@ -13,133 +45,130 @@ struct EditQuestTemplate<'a> {
     current_profile: &'a Option<ProfileRenderInfo>,
     title: &'a String,
     slug: &'a String,
+    posts: &'a Vec<QmPost>,
+    chapters: &'a Vec<QmChapter>,
 }
 
 #[get("/edit/{slug}")]
-async fn edit_quest(
-    app_state: web::Data<AppState>,
-    info: web::Path<(String,)>,
-    request: HttpRequest,
-) -> Result<impl Responder> {
-    let (slug,) = info.into_inner();
-    let SessionInfo {
-        account_id,
-        current_profile,
-        ..
-    } = app_state.require_session(request).await?;
+async fn edit_quest(app_state: web::Data<AppState>, quest: OwnedQuest) -> Result<impl Responder> {
+    let posts: Vec<QmPost> = sqlx::query_as(
+        r#"
+        select
+          quest_post.id,
+          title,
+          state,
+          quest_post.created_at,
+          published_at,
+          winner.body as winning_command,
+          chapter
+        from quest_post
+          left join quest_comment winner on winning_command = winner.id
+        where quest = $1
+        order by quest_post.created_at desc
+        "#,
+    )
+    .bind(quest.id)
+    .fetch_all(&app_state.db_pool)
+    .await
+    .context("Failed to fetch quest posts")?;
 
-    let (title,): (String,) = sqlx::query_as(
+    let chapters: Vec<QmChapter> = sqlx::query_as(
         r#"
-        select title
-        from quest
-        where questmaster = $1
-        and slug = $2
+        select quest_chapter.id, quest_chapter.title, count(quest_post.id) as post_count
+        from quest_chapter
+          left join quest_post on quest_post.chapter = quest_chapter.id
+        where quest_chapter.quest = $1
+        group by quest_chapter.id
+        order by quest_chapter.created_at asc
         "#,
     )
-    .bind(account_id)
-    .bind(&slug)
-    .fetch_one(&app_state.db_pool)
+    .bind(quest.id)
+    .fetch_all(&app_state.db_pool)
     .await
-    .context("Failed to fetch quest")?;
+    .context("Failed to fetch chapters")?;
 
     Ok(EditQuestTemplate {
         config: &app_state.config,
         logged_in: true,
-        current_profile: &current_profile,
-        title: &title,
-        slug: &slug,
+        current_profile: &quest.session.current_profile,
+        title: &quest.title,
+        slug: &quest.slug,
+        posts: &posts,
+        chapters: &chapters,
     }
     .to_response())
 }
 
-#[derive(Template)]
-#[template(path = "qm/markup_error.html")]
-struct MarkupErrorTemplate<'a> {
-    config: &'a AppConfig,
-    logged_in: bool,
-    current_profile: &'a Option<ProfileRenderInfo>,
-    error: &'a str,
-    raw: &'a str,
-}
-
 #[derive(Deserialize)]
 struct NewQuestPostForm {
     title: String,
     body: String,
+    /// Initial state; either a draft or one of the published states.
+    state: PostState,
 }
 
 #[post("/edit/{slug}")]
 async fn edit_quest_submit(
     app_state: web::Data<AppState>,
-    info: web::Path<(String,)>,
+    quest: OwnedQuest,
     form: web::Form<NewQuestPostForm>,
-    request: HttpRequest,
 ) -> Result<impl Responder> {
-    let (slug,) = info.into_inner();
-    let SessionInfo {
-        account_id,
-        current_profile,
-        ..
-    } = app_state.require_session(request).await?;
-    // TODO - Must be the QM of this quest.
+    let current_profile = &quest.session.current_profile;
 
-    let html = match markup::to_html(form.body.as_str()) {
-        Ok(html) => html,
-        Err(err) => {
-            let error_text = format!("{}", err);
-            return Ok(MarkupErrorTemplate {
-                config: &app_state.config,
-                logged_in: true,
-                current_profile: &current_profile,
-                error: error_text.as_str(),
-                raw: &form.body,
-            }
-            .to_response());
-        }
-    };
+    if form.state != PostState::Draft && !PostState::Draft.can_transition_to(form.state) {
+        return Err(Error::AppError(format!(
+            "A new post can't start out as \"{}\".",
+            form.state.label()
+        )));
+    }
+
+    let mentions = known_mentions(&app_state.db_pool, &form.body).await?;
+    let rendered = posts::render_post(&app_state.config, &form.body, &[], &mentions);
 
     let mut transaction = app_state
         .db_pool
         .begin()
         .await
         .context("Failed to create transaction")?;
-
-    let (quest_id,): (Uuid,) = sqlx::query_as(
-        r#"
-        select id
-        from quest
-        where questmaster = $1 and slug = $2
-        "#,
-    )
-    .bind(account_id)
-    .bind(&slug)
-    .fetch_one(&mut *transaction)
-    .await
-    .context("Failed to fetch quest id")?;
-
+    let post_id = Uuid::now_v6(&app_state.uuid_seed);
     sqlx::query(
         r#"
-        insert into quest_post (id, quest, title, body_markup, body_html)
-        values ($1, $2, $3, $4, $5)
+        insert into quest_post
+          (id, quest, title, body_markup, body_html, excerpt, state, published_at)
+        values ($1, $2, $3, $4, $5, $6, $7, case when $8 then current_timestamp end)
         "#,
     )
-    .bind(Uuid::now_v6(&app_state.uuid_seed))
-    .bind(quest_id)
+    .bind(post_id)
+    .bind(quest.id)
     .bind(&form.title)
     .bind(&form.body)
-    .bind(&html)
+    .bind(&rendered.html)
+    .bind(&rendered.excerpt)
+    .bind(form.state)
+    .bind(form.state.is_published())
     .execute(&mut *transaction)
     .await
     .context("Failed to post update")?;
-
+    posts::store_rolls(&mut transaction, &app_state, post_id, &rendered.new_rolls).await?;
     transaction.commit().await.context("Failed to commit")?;
 
     Ok(partials::MessagePageTemplate {
         config: &app_state.config,
         logged_in: true,
-        current_profile: &current_profile,
+        current_profile,
         page_title: &Some("Update successful"),
-        message: "Update posted successfully.",
+        message: match (form.state.is_published(), rendered.warnings.is_empty()) {
+            (true, true) => "Update posted successfully.",
+            (false, true) => "Update saved as a draft.",
+            (true, false) => {
+                "Update posted, but some of its markup couldn't be rendered and is shown \
+                as text. Edit the post and use the Preview button to see what went wrong."
+            }
+            (false, false) => {
+                "Update saved as a draft, but some of its markup couldn't be rendered and is \
+                shown as text. Edit the post and use the Preview button to see what went wrong."
+            }
+        },
     }
     .to_response())
 }
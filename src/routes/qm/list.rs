@@ -1,5 +1,6 @@
 use std::vec::Vec;
 
+use crate::permissions::Questmaster;
 use crate::routes::prelude::*;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
@@ -25,15 +26,12 @@ struct ListQuestTemplate<'a> {
 #[get("/")]
 async fn list_quests(
     app_state: web::Data<AppState>,
-    request: HttpRequest,
-) -> Result<impl Responder> {
-    let SessionInfo {
+    Questmaster(SessionInfo {
         account_id,
         current_profile,
         ..
-    } = app_state.require_session(request).await?;
-    // TODO - Must be QM to view this page.
-
+    }): Questmaster,
+) -> Result<impl Responder> {
     let quests: Vec<ListQuest> = sqlx::query_as(
         r#"
         select title, slug
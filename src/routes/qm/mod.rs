@@ -1,13 +1,23 @@
+mod chapter;
 mod edit;
 mod list;
+mod moderate;
 mod new;
+mod revise;
+mod settings;
+mod state;
 
 use actix_web::dev::ServiceFactory;
 use actix_web::dev::ServiceRequest;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    let scope = chapter::add_routes(scope);
     let scope = edit::add_routes(scope);
     let scope = list::add_routes(scope);
+    let scope = moderate::add_routes(scope);
     let scope = new::add_routes(scope);
+    let scope = revise::add_routes(scope);
+    let scope = settings::add_routes(scope);
+    let scope = state::add_routes(scope);
     scope
 }
@@ -0,0 +1,225 @@
+use chrono::{DateTime, Utc};
+
+use crate::model::PostState;
+use crate::permissions::OwnedQuest;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope
+        .service(list_commands)
+        .service(exclude_command)
+        .service(restore_command)
+}
+
+/// Output object for the post whose commands are being moderated.
+#[derive(sqlx::FromRow, Debug)]
+struct ModeratedPost {
+    id: Uuid,
+    title: Option<String>,
+    state: PostState,
+}
+
+/// Output object for the command list query.
+#[derive(sqlx::FromRow, Debug)]
+struct Command {
+    id: Uuid,
+    body: String,
+    created_at: DateTime<Utc>,
+    username: Option<String>,
+    qm_excluded: bool,
+    qm_exclusion_reason: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "qm/commands.html")]
+struct CommandsTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    title: &'a str,
+    slug: &'a str,
+    post: &'a Option<ModeratedPost>,
+    commands: &'a Vec<Command>,
+    /// Whether the commands can still be excluded or restored.
+    open: bool,
+}
+
+/// Lists the commands on the latest published post of a quest.
+#[get("/edit/{slug}/commands")]
+async fn list_commands(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+) -> Result<impl Responder> {
+    let post: Option<ModeratedPost> = sqlx::query_as(
+        r#"
+        select id, title, state
+        from quest_post
+        where quest = $1 and published_at is not null
+        order by published_at desc
+        limit 1
+        "#,
+    )
+    .bind(quest.id)
+    .fetch_optional(&app_state.db_pool)
+    .await
+    .context("Failed to fetch latest post")?;
+
+    let commands: Vec<Command> = match &post {
+        None => Vec::new(),
+        Some(post) => sqlx::query_as(
+            r#"
+            select
+              quest_comment.id,
+              body,
+              created_at,
+              profile.username,
+              qm_excluded,
+              qm_exclusion_reason
+            from quest_comment
+              left join profile on commenter_profile = profile.id
+            where quest_post = $1
+              and comment_type = 'command'::quest_comment_type
+            order by created_at asc
+            "#,
+        )
+        .bind(post.id)
+        .fetch_all(&app_state.db_pool)
+        .await
+        .context("Failed to fetch commands")?,
+    };
+
+    Ok(CommandsTemplate {
+        config: &app_state.config,
+        logged_in: true,
+        current_profile: &quest.session.current_profile,
+        title: &quest.title,
+        slug: &quest.slug,
+        post: &post,
+        commands: &commands,
+        open: post.as_ref().is_some_and(|post| is_open(post.state)),
+    }
+    .to_response())
+}
+
+#[derive(Deserialize)]
+struct ExcludeForm {
+    reason: String,
+}
+
+/// Maximum length of an exclusion reason, in characters.
+const MAX_REASON_LENGTH: usize = 500;
+
+/// Excludes a command from voting, with an optional reason shown to players.
+#[post("/edit/{slug}/commands/{comment_id}/exclude")]
+async fn exclude_command(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+    form: web::Form<ExcludeForm>,
+) -> Result<impl Responder> {
+    let (_, comment_id) = info.into_inner();
+
+    let reason = form.reason.trim();
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(Error::AppError(format!(
+            "Exclusion reasons can be at most {MAX_REASON_LENGTH} characters long."
+        )));
+    }
+
+    set_excluded(
+        &app_state,
+        &quest,
+        comment_id,
+        true,
+        Some(reason).filter(|reason| !reason.is_empty()),
+    )
+    .await?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}/commands", quest.slug)).see_other())
+}
+
+/// Undoes the exclusion of a command.
+#[post("/edit/{slug}/commands/{comment_id}/restore")]
+async fn restore_command(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+) -> Result<impl Responder> {
+    let (_, comment_id) = info.into_inner();
+
+    set_excluded(&app_state, &quest, comment_id, false, None).await?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}/commands", quest.slug)).see_other())
+}
+
+/// Whether the commands on a post can still be moderated. Once a post is
+/// finalized, its winning command has been chosen.
+fn is_open(state: PostState) -> bool {
+    state.accepts_commands() || state.accepts_votes()
+}
+
+/// Sets the exclusion status of a command on the latest post of one of the
+/// QM's quests.
+async fn set_excluded(
+    app_state: &AppState,
+    quest: &OwnedQuest,
+    comment_id: Uuid,
+    excluded: bool,
+    reason: Option<&str>,
+) -> Result<()> {
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+
+    // Lock the post so that it can't be finalized with a stale set of
+    // commands.
+    let (post_id, state): (Uuid, PostState) = sqlx::query_as(
+        r#"
+        select id, state
+        from quest_post
+        where quest = $1 and published_at is not null
+        order by published_at desc
+        limit 1
+        for update
+        "#,
+    )
+    .bind(quest.id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch latest post")?
+    .ok_or_else(|| Error::NotFoundError("This quest has no published posts.".to_string()))?;
+    if !is_open(state) {
+        return Err(Error::AppError(
+            "The vote on this post has closed, so its commands can't be changed.".to_string(),
+        ));
+    }
+
+    if sqlx::query(
+        r#"
+        update quest_comment
+        set qm_excluded = $1, qm_exclusion_reason = $2
+        where id = $3
+          and quest_post = $4
+          and comment_type = 'command'::quest_comment_type
+        "#,
+    )
+    .bind(excluded)
+    .bind(reason)
+    .bind(comment_id)
+    .bind(post_id)
+    .execute(&mut *transaction)
+    .await
+    .db_context("Failed to update command exclusion")?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::NotFoundError(
+            "That command isn't on the latest post.".to_string(),
+        ));
+    }
+
+    transaction.commit().await.context("Failed to commit")?;
+    Ok(())
+}
@@ -2,6 +2,7 @@ use actix_web::dev::ServiceFactory;
 use actix_web::dev::ServiceRequest;
 
 use crate::partials;
+use crate::permissions::Questmaster;
 use crate::routes::prelude::*;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
@@ -22,15 +23,10 @@ struct NewQuestTemplate<'a> {
 #[get("/new")]
 async fn create_new_quest_form(
     app_state: web::Data<AppState>,
-    request: HttpRequest,
+    Questmaster(SessionInfo {
+        current_profile, ..
+    }): Questmaster,
 ) -> Result<impl Responder> {
-    let SessionInfo {
-        account_id,
-        current_profile,
-        ..
-    } = app_state.require_session(request).await?;
-    // TODO - Must be QM to view this page.
-
     Ok(NewQuestTemplate {
         config: &app_state.config,
         logged_in: true,
@@ -109,15 +105,12 @@ struct NewQuestForm {
 async fn create_new_quest_submit(
     app_state: web::Data<AppState>,
     form: web::Form<NewQuestForm>,
-    request: HttpRequest,
-) -> Result<impl Responder> {
-    let SessionInfo {
+    Questmaster(SessionInfo {
         account_id,
         current_profile,
         ..
-    } = app_state.require_session(request).await?;
-    // TODO - Must be QM to view this page.
-
+    }): Questmaster,
+) -> Result<impl Responder> {
     let mut transaction = app_state
         .db_pool
         .begin()
@@ -0,0 +1,347 @@
+use chrono::{DateTime, Utc};
+use similar::{ChangeTag, TextDiff};
+
+use crate::permissions::OwnedQuest;
+use crate::posts::{self, RenderedPost};
+use crate::routes::markup::known_mentions;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope
+        .service(edit_post)
+        .service(edit_post_submit)
+        .service(view_revision)
+        .service(restore_revision)
+}
+
+/// Output object for the post being edited.
+#[derive(sqlx::FromRow, Debug)]
+struct EditedPost {
+    id: Uuid,
+    title: Option<String>,
+    body_markup: String,
+    edited_at: Option<DateTime<Utc>>,
+}
+
+/// Output object for the revision list query.
+#[derive(sqlx::FromRow, Debug)]
+struct RevisionListing {
+    id: Uuid,
+    title: Option<String>,
+    created_at: DateTime<Utc>,
+    replaced_at: DateTime<Utc>,
+}
+
+/// Output object for a single revision.
+#[derive(sqlx::FromRow, Debug)]
+struct Revision {
+    id: Uuid,
+    title: Option<String>,
+    body_markup: String,
+    created_at: DateTime<Utc>,
+    replaced_at: DateTime<Utc>,
+}
+
+/// A line of a diff between two versions of a post's markup.
+struct DiffLine {
+    tag: ChangeTag,
+    text: String,
+}
+
+impl DiffLine {
+    fn sign(&self) -> &'static str {
+        match self.tag {
+            ChangeTag::Delete => "-",
+            ChangeTag::Insert => "+",
+            ChangeTag::Equal => " ",
+        }
+    }
+
+    fn is_delete(&self) -> bool {
+        self.tag == ChangeTag::Delete
+    }
+
+    fn is_insert(&self) -> bool {
+        self.tag == ChangeTag::Insert
+    }
+}
+
+/// Line-by-line diff from `old` to `new`.
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: change.tag(),
+            text: change.value().trim_end_matches('\n').to_string(),
+        })
+        .collect()
+}
+
+async fn find_post(
+    db_pool: &sqlx::postgres::PgPool,
+    quest: &OwnedQuest,
+    post_id: Uuid,
+) -> Result<EditedPost> {
+    quest.check_post(db_pool, post_id).await?;
+    Ok(sqlx::query_as(
+        r#"
+        select id, title, body_markup, edited_at
+        from quest_post
+        where id = $1
+        "#,
+    )
+    .bind(post_id)
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to fetch quest post")?)
+}
+
+/// Replaces the contents of a post, saving the previous version as a
+/// revision. Returns whether anything changed.
+async fn revise(
+    app_state: &AppState,
+    post_id: Uuid,
+    title: Option<&str>,
+    body_markup: &str,
+    rendered: &RenderedPost,
+) -> Result<bool> {
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+
+    // Lock the post so that concurrent edits can't lose a revision.
+    let (current_title, current_markup): (Option<String>, String) = sqlx::query_as(
+        r#"
+        select title, body_markup
+        from quest_post
+        where id = $1
+        for update
+        "#,
+    )
+    .bind(post_id)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch quest post")?;
+    if current_title.as_deref() == title && current_markup == body_markup {
+        return Ok(false);
+    }
+
+    // The current version was written when the previous one was replaced, or
+    // when the post was created if it has never been edited.
+    sqlx::query(
+        r#"
+        insert into quest_post_revision
+          (id, quest_post, title, body_markup, body_html, created_at)
+        select $1, id, title, body_markup, body_html, coalesce(
+          (
+            select max(replaced_at)
+            from quest_post_revision
+            where quest_post = $2
+          ),
+          created_at
+        )
+        from quest_post
+        where id = $2
+        "#,
+    )
+    .bind(Uuid::now_v6(&app_state.uuid_seed))
+    .bind(post_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save revision")?;
+
+    sqlx::query(
+        r#"
+        update quest_post
+        set
+          title = $1,
+          body_markup = $2,
+          body_html = $3,
+          excerpt = $4,
+          edited_at = case
+            when published_at is not null then current_timestamp
+            else edited_at
+          end
+        where id = $5
+        "#,
+    )
+    .bind(title)
+    .bind(body_markup)
+    .bind(&rendered.html)
+    .bind(&rendered.excerpt)
+    .bind(post_id)
+    .execute(&mut *transaction)
+    .await
+    .db_context("Failed to update quest post")?;
+    posts::store_rolls(&mut transaction, app_state, post_id, &rendered.new_rolls).await?;
+
+    transaction.commit().await.context("Failed to commit")?;
+    Ok(true)
+}
+
+#[derive(Template)]
+#[template(path = "qm/revise.html")]
+struct EditPostTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    quest_title: &'a str,
+    slug: &'a str,
+    post: &'a EditedPost,
+    revisions: &'a Vec<RevisionListing>,
+}
+
+/// Form for editing an existing post, along with its earlier revisions.
+#[get("/edit/{slug}/post/{post_id}/edit")]
+async fn edit_post(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+) -> Result<impl Responder> {
+    let (_, post_id) = info.into_inner();
+    let post = find_post(&app_state.db_pool, &quest, post_id).await?;
+
+    let revisions: Vec<RevisionListing> = sqlx::query_as(
+        r#"
+        select id, title, created_at, replaced_at
+        from quest_post_revision
+        where quest_post = $1
+        order by replaced_at desc
+        "#,
+    )
+    .bind(post.id)
+    .fetch_all(&app_state.db_pool)
+    .await
+    .context("Failed to fetch revisions")?;
+
+    Ok(EditPostTemplate {
+        config: &app_state.config,
+        logged_in: true,
+        current_profile: &quest.session.current_profile,
+        quest_title: &quest.title,
+        slug: &quest.slug,
+        post: &post,
+        revisions: &revisions,
+    }
+    .to_response())
+}
+
+#[derive(Deserialize)]
+struct EditPostForm {
+    title: String,
+    body: String,
+}
+
+#[post("/edit/{slug}/post/{post_id}/edit")]
+async fn edit_post_submit(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+    form: web::Form<EditPostForm>,
+) -> Result<impl Responder> {
+    let (_, post_id) = info.into_inner();
+    quest.check_post(&app_state.db_pool, post_id).await?;
+
+    let stored = posts::stored_rolls(&app_state.db_pool, post_id).await?;
+    let mentions = known_mentions(&app_state.db_pool, &form.body).await?;
+    let rendered = posts::render_post(&app_state.config, &form.body, &stored, &mentions);
+
+    let title = form.title.trim();
+    revise(
+        &app_state,
+        post_id,
+        Some(title).filter(|title| !title.is_empty()),
+        &form.body,
+        &rendered,
+    )
+    .await?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}/post/{post_id}/edit", quest.slug)).see_other())
+}
+
+#[derive(Template)]
+#[template(path = "qm/revision.html")]
+struct RevisionTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    slug: &'a str,
+    post: &'a EditedPost,
+    revision: &'a Revision,
+    diff: &'a Vec<DiffLine>,
+}
+
+async fn find_revision(
+    db_pool: &sqlx::postgres::PgPool,
+    post_id: Uuid,
+    revision_id: Uuid,
+) -> Result<Revision> {
+    sqlx::query_as(
+        r#"
+        select id, title, body_markup, created_at, replaced_at
+        from quest_post_revision
+        where id = $1 and quest_post = $2
+        "#,
+    )
+    .bind(revision_id)
+    .bind(post_id)
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch revision")?
+    .ok_or_else(|| Error::NotFoundError("That revision doesn't exist.".to_string()))
+}
+
+/// Shows what changed between a revision and the current version of a post.
+#[get("/edit/{slug}/post/{post_id}/revisions/{revision_id}")]
+async fn view_revision(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid, Uuid)>,
+) -> Result<impl Responder> {
+    let (_, post_id, revision_id) = info.into_inner();
+    let post = find_post(&app_state.db_pool, &quest, post_id).await?;
+    let revision = find_revision(&app_state.db_pool, post.id, revision_id).await?;
+
+    Ok(RevisionTemplate {
+        config: &app_state.config,
+        logged_in: true,
+        current_profile: &quest.session.current_profile,
+        slug: &quest.slug,
+        diff: &diff_lines(&revision.body_markup, &post.body_markup),
+        post: &post,
+        revision: &revision,
+    }
+    .to_response())
+}
+
+/// Makes an earlier revision the current version of the post. The version
+/// being replaced is kept as a revision too, so this can be undone.
+#[post("/edit/{slug}/post/{post_id}/revisions/{revision_id}/restore")]
+async fn restore_revision(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid, Uuid)>,
+) -> Result<impl Responder> {
+    let (_, post_id, revision_id) = info.into_inner();
+    quest.check_post(&app_state.db_pool, post_id).await?;
+    let revision = find_revision(&app_state.db_pool, post_id, revision_id).await?;
+
+    // Render again rather than reusing the old HTML, in case the markup
+    // renderer has changed since. Its dice rolls are still stored, so they
+    // come out the same.
+    let stored = posts::stored_rolls(&app_state.db_pool, post_id).await?;
+    let mentions = known_mentions(&app_state.db_pool, &revision.body_markup).await?;
+    let rendered = posts::render_post(&app_state.config, &revision.body_markup, &stored, &mentions);
+    revise(
+        &app_state,
+        post_id,
+        revision.title.as_deref(),
+        &revision.body_markup,
+        &rendered,
+    )
+    .await?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}/post/{post_id}/edit", quest.slug)).see_other())
+}
@@ -0,0 +1,211 @@
+use crate::markup;
+use crate::model::QuestPublishState;
+use crate::permissions::OwnedQuest;
+use crate::routes::markup::known_mentions;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(quest_settings).service(quest_settings_submit)
+}
+
+/// Maximum lengths of quest fields, in characters. These match the database
+/// column types.
+const MAX_TITLE_LENGTH: usize = 250;
+const MAX_SHORT_DESCRIPTION_LENGTH: usize = 250;
+const MAX_LONG_DESCRIPTION_LENGTH: usize = 5000;
+
+/// Output object for quest settings.
+#[derive(sqlx::FromRow, Debug)]
+struct QuestSettings {
+    title: String,
+    short_description: Option<String>,
+    long_description: Option<String>,
+    publish_state: QuestPublishState,
+    general_access: bool,
+    general_commenting: bool,
+    listed_in_feeds: bool,
+    require_log_in_to_view: bool,
+}
+
+#[derive(Template)]
+#[template(path = "qm/settings.html")]
+struct QuestSettingsTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    slug: &'a str,
+    settings: &'a QuestSettings,
+    messages: &'a Vec<String>,
+}
+
+async fn render_settings(
+    app_state: &AppState,
+    quest: &OwnedQuest,
+    messages: &Vec<String>,
+) -> Result<HttpResponse> {
+    let settings: QuestSettings = sqlx::query_as(
+        r#"
+        select
+          title,
+          short_description,
+          long_description,
+          publish_state,
+          general_access,
+          general_commenting,
+          listed_in_feeds,
+          require_log_in_to_view
+        from quest
+        where id = $1
+        "#,
+    )
+    .bind(quest.id)
+    .fetch_one(&app_state.db_pool)
+    .await
+    .context("Failed to fetch quest settings")?;
+
+    Ok(QuestSettingsTemplate {
+        config: &app_state.config,
+        logged_in: true,
+        current_profile: &quest.session.current_profile,
+        slug: &quest.slug,
+        settings: &settings,
+        messages,
+    }
+    .to_response())
+}
+
+#[get("/edit/{slug}/settings")]
+async fn quest_settings(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+) -> Result<impl Responder> {
+    render_settings(&app_state, &quest, &Vec::new()).await
+}
+
+#[derive(Deserialize)]
+struct QuestSettingsForm {
+    title: String,
+    short_description: String,
+    long_description: String,
+    publish_state: QuestPublishState,
+    // Unchecked checkboxes aren't submitted at all.
+    #[serde(default)]
+    general_access: bool,
+    #[serde(default)]
+    general_commenting: bool,
+    #[serde(default)]
+    listed_in_feeds: bool,
+    #[serde(default)]
+    require_log_in_to_view: bool,
+}
+
+/// Trims a description and renders it as markup, like posts are, so anything
+/// which can't be rendered is shown as text. Empty descriptions become `None`.
+fn render_description(
+    options: &markup::Options,
+    description: &str,
+    name: &str,
+    max_length: usize,
+) -> Result<Option<(String, markup::Rendered)>> {
+    let description = description.trim();
+    if description.is_empty() {
+        return Ok(None);
+    }
+    if description.chars().count() > max_length {
+        return Err(Error::AppError(format!(
+            "The {name} can be at most {max_length} characters long."
+        )));
+    }
+    let rendered = markup::to_html_tolerant(description, options);
+    Ok(Some((description.to_string(), rendered)))
+}
+
+#[post("/edit/{slug}/settings")]
+async fn quest_settings_submit(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    form: web::Form<QuestSettingsForm>,
+) -> Result<impl Responder> {
+    let title = form.title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(Error::AppError(format!(
+            "Quest titles must be between 1 and {MAX_TITLE_LENGTH} characters long."
+        )));
+    }
+    let short_mentions = known_mentions(&app_state.db_pool, &form.short_description).await?;
+    let short_description = render_description(
+        &markup::Options {
+            mentions: &short_mentions,
+            ..app_state.config.markup_options()
+        },
+        &form.short_description,
+        "short description",
+        MAX_SHORT_DESCRIPTION_LENGTH,
+    )?;
+    let long_mentions = known_mentions(&app_state.db_pool, &form.long_description).await?;
+    let long_description = render_description(
+        &markup::Options {
+            mentions: &long_mentions,
+            ..app_state.config.markup_options()
+        },
+        &form.long_description,
+        "long description",
+        MAX_LONG_DESCRIPTION_LENGTH,
+    )?;
+
+    let mut messages = vec!["Saved quest settings".to_string()];
+    for (name, description) in [
+        ("short description", &short_description),
+        ("long description", &long_description),
+    ] {
+        let warnings = description
+            .iter()
+            .flat_map(|(_, rendered)| &rendered.warnings);
+        for warning in warnings {
+            messages.push(format!(
+                "Some of the markup in the {name} couldn't be rendered and is shown as text: \
+                {warning}"
+            ));
+        }
+    }
+    let (short_description, short_description_html) = short_description
+        .map(|(description, rendered)| (description, rendered.html))
+        .unzip();
+    let (long_description, long_description_html) = long_description
+        .map(|(description, rendered)| (description, rendered.html))
+        .unzip();
+
+    sqlx::query(
+        r#"
+        update quest
+        set
+          title = $1,
+          short_description = $2,
+          short_description_html = $3,
+          long_description = $4,
+          long_description_html = $5,
+          publish_state = $6,
+          general_access = $7,
+          general_commenting = $8,
+          listed_in_feeds = $9,
+          require_log_in_to_view = $10
+        where id = $11
+        "#,
+    )
+    .bind(title)
+    .bind(short_description)
+    .bind(short_description_html)
+    .bind(long_description)
+    .bind(long_description_html)
+    .bind(form.publish_state)
+    .bind(form.general_access)
+    .bind(form.general_commenting)
+    .bind(form.listed_in_feeds)
+    .bind(form.require_log_in_to_view)
+    .bind(quest.id)
+    .execute(&app_state.db_pool)
+    .await
+    .db_context("Failed to update quest settings")?;
+
+    render_settings(&app_state, &quest, &messages).await
+}
@@ -0,0 +1,117 @@
+use crate::model::{PostState, VoteMode};
+use crate::permissions::OwnedQuest;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(change_post_state)
+}
+
+#[derive(Deserialize)]
+struct PostStateForm {
+    state: PostState,
+    /// How votes are counted; only used when opening voting.
+    vote_mode: Option<VoteMode>,
+}
+
+/// Move a post along its lifecycle, e.g. publishing a draft or opening voting.
+#[post("/edit/{slug}/post/{post_id}/state")]
+async fn change_post_state(
+    app_state: web::Data<AppState>,
+    quest: OwnedQuest,
+    info: web::Path<(String, Uuid)>,
+    form: web::Form<PostStateForm>,
+) -> Result<impl Responder> {
+    let (_, post_id) = info.into_inner();
+    quest.check_post(&app_state.db_pool, post_id).await?;
+
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+
+    // Lock the post so that concurrent transitions can't skip validation.
+    let (current_state,): (PostState,) = sqlx::query_as(
+        r#"
+        select state
+        from quest_post
+        where id = $1
+        for update
+        "#,
+    )
+    .bind(post_id)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch post state")?;
+
+    if !current_state.can_transition_to(form.state) {
+        return Err(Error::AppError(format!(
+            "A post can't go from \"{}\" to \"{}\".",
+            current_state.label(),
+            form.state.label()
+        )));
+    }
+
+    sqlx::query(
+        r#"
+        update quest_post
+        set
+          state = $1,
+          published_at = case
+            when $2 then coalesce(published_at, current_timestamp)
+            else published_at
+          end
+        where id = $3
+        "#,
+    )
+    .bind(form.state)
+    .bind(form.state.is_published())
+    .bind(post_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update post state")?;
+
+    if form.state.accepts_votes() {
+        if let Some(vote_mode) = form.vote_mode {
+            sqlx::query(
+                r#"
+                update quest_post
+                set vote_mode = $1
+                where id = $2
+                "#,
+            )
+            .bind(vote_mode)
+            .bind(post_id)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to set vote mode")?;
+        }
+    } else if current_state.accepts_votes() {
+        // Closing the vote; record the winner. Ties go to the earliest command.
+        sqlx::query(
+            r#"
+            update quest_post
+            set winning_command = (
+              select quest_comment.id
+              from quest_comment
+                join quest_vote on quest_vote.command = quest_comment.id
+              where quest_comment.quest_post = $1
+                and comment_type = 'command'::quest_comment_type
+                and not qm_excluded
+              group by quest_comment.id
+              order by count(*) desc, quest_comment.created_at asc
+              limit 1
+            )
+            where id = $1
+            "#,
+        )
+        .bind(post_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to record winning command")?;
+    }
+
+    transaction.commit().await.context("Failed to commit")?;
+
+    Ok(web::Redirect::to(format!("/qm/edit/{}", quest.slug)).see_other())
+}
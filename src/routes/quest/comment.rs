@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::model::{CommentType, PostState};
+use crate::permissions::QuestAccess;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(list_comments).service(post_comment)
+}
+
+/// Maximum length of a comment body, in characters.
+const MAX_COMMENT_LENGTH: usize = 10000;
+
+/// Output object for comment list query.
+#[derive(sqlx::FromRow, Debug)]
+struct Comment {
+    id: Uuid,
+    reply_to: Option<Uuid>,
+    comment_type: CommentType,
+    qm_excluded: bool,
+    qm_exclusion_reason: Option<String>,
+    body: String,
+    created_at: DateTime<Utc>,
+    username: Option<String>,
+    display_name: Option<String>,
+    /// How deeply nested the comment is in its thread. Filled in by `thread`.
+    #[sqlx(skip)]
+    depth: usize,
+}
+
+/// Deepest level of indentation shown for replies.
+const MAX_DISPLAY_DEPTH: usize = 6;
+
+/// Orders comments depth-first, so that replies directly follow the comment
+/// they are replying to. Expects the input to be in chronological order.
+fn thread(comments: Vec<Comment>) -> Vec<Comment> {
+    let mut children: HashMap<Option<Uuid>, Vec<Comment>> = HashMap::new();
+    let ids: Vec<Uuid> = comments.iter().map(|comment| comment.id).collect();
+    for comment in comments.into_iter() {
+        // Orphaned replies are shown at the top level rather than dropped.
+        let parent = comment.reply_to.filter(|reply_to| ids.contains(reply_to));
+        children.entry(parent).or_default().push(comment);
+    }
+
+    let mut threaded = Vec::new();
+    let mut stack: Vec<(Comment, usize)> = Vec::new();
+    if let Some(roots) = children.remove(&None) {
+        stack.extend(roots.into_iter().rev().map(|comment| (comment, 0)));
+    }
+    while let Some((mut comment, depth)) = stack.pop() {
+        if let Some(replies) = children.remove(&Some(comment.id)) {
+            stack.extend(replies.into_iter().rev().map(|reply| (reply, depth + 1)));
+        }
+        comment.depth = depth.min(MAX_DISPLAY_DEPTH);
+        threaded.push(comment);
+    }
+    threaded
+}
+
+#[derive(Template)]
+#[template(path = "quest/comments.html")]
+struct CommentsTemplate<'a> {
+    username: &'a str,
+    slug: &'a str,
+    post_id: &'a Uuid,
+    comments: &'a Vec<Comment>,
+    /// Why the current user can't comment, if they can't.
+    closed_notice: Option<&'a str>,
+    accepts_commands: bool,
+    error: &'a Option<String>,
+}
+
+/// Explains why the current user can't comment on a post, if they can't.
+fn closed_notice(
+    post_state: PostState,
+    access: &QuestAccess,
+    account_id: Option<Uuid>,
+    has_profile: bool,
+) -> Option<&'static str> {
+    if !post_state.is_published() {
+        Some("Comments open once this post is published.")
+    } else if account_id.is_none() {
+        Some("Log in to comment.")
+    } else if !access.can_comment(account_id) {
+        Some("Only invited players can comment on this quest.")
+    } else if !has_profile {
+        Some("Choose a profile to comment.")
+    } else {
+        None
+    }
+}
+
+/// Checks that the post exists under the given quest and returns its ID and
+/// state.
+async fn find_post(
+    db_pool: &sqlx::postgres::PgPool,
+    access: &QuestAccess,
+    account_id: Option<Uuid>,
+    post_id: Uuid,
+) -> Result<(Uuid, PostState)> {
+    sqlx::query_as(
+        r#"
+        select id, state
+        from quest_post
+        where quest = $1 and id = $2
+          and (published_at is not null or $3)
+        "#,
+    )
+    .bind(access.quest_id)
+    .bind(post_id)
+    .bind(access.is_questmaster(account_id))
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch quest post")?
+    .ok_or_else(|| Error::NotFoundError("That quest post doesn't exist.".to_string()))
+}
+
+async fn fetch_comments(db_pool: &sqlx::postgres::PgPool, post_id: Uuid) -> Result<Vec<Comment>> {
+    let comments: Vec<Comment> = sqlx::query_as(
+        r#"
+        select
+          quest_comment.id,
+          reply_to,
+          comment_type,
+          qm_excluded,
+          qm_exclusion_reason,
+          body,
+          created_at,
+          profile.username,
+          profile.display_name
+        from quest_comment
+          left join profile on commenter_profile = profile.id
+        where quest_post = $1
+        order by created_at asc
+        "#,
+    )
+    .bind(post_id)
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch comments")?;
+    Ok(thread(comments))
+}
+
+/// Comment thread for a post, for injection via HTMX.
+#[get("/{slug}/post/{post_id}/comments")]
+async fn list_comments(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String, Uuid)>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug, post_id) = info.into_inner();
+    let session_info = app_state.get_session(request).await.transpose()?;
+    let account_id = session_info.as_ref().map(|info| info.account_id);
+    let access = QuestAccess::load(&app_state.db_pool, &username, &slug, account_id).await?;
+    access.check_view(account_id)?;
+
+    let (post_id, post_state) = find_post(&app_state.db_pool, &access, account_id, post_id).await?;
+    let comments = fetch_comments(&app_state.db_pool, post_id).await?;
+
+    Ok(CommentsTemplate {
+        username: &username,
+        slug: &slug,
+        post_id: &post_id,
+        comments: &comments,
+        closed_notice: closed_notice(
+            post_state,
+            &access,
+            account_id,
+            session_info.is_some_and(|info| info.current_profile.is_some()),
+        ),
+        accepts_commands: post_state.accepts_commands(),
+        error: &None,
+    }
+    .to_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct CommentForm {
+    comment_type: CommentType,
+    reply_to: Option<Uuid>,
+    body: String,
+}
+
+/// Validates a new comment before it reaches the database. The post is locked
+/// until the end of the transaction, so that it can't stop accepting commands
+/// before the comment is recorded.
+async fn validate_comment(
+    connection: &mut PgConnection,
+    post_id: Uuid,
+    form: &CommentForm,
+) -> Result<()> {
+    let (post_state,): (PostState,) = sqlx::query_as(
+        r#"
+        select state
+        from quest_post
+        where id = $1
+        for update
+        "#,
+    )
+    .bind(post_id)
+    .fetch_one(&mut *connection)
+    .await
+    .context("Failed to lock post")?;
+    if !post_state.is_published() {
+        return Err(Error::AppError(
+            "This post hasn't been published yet.".to_string(),
+        ));
+    }
+    if form.comment_type == CommentType::Command && !post_state.accepts_commands() {
+        return Err(Error::AppError(
+            "This post isn't accepting commands anymore.".to_string(),
+        ));
+    }
+    if form.body.trim().is_empty() {
+        return Err(Error::AppError("The comment can't be empty.".to_string()));
+    }
+    if form.body.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(Error::AppError(format!(
+            "Comments can be at most {MAX_COMMENT_LENGTH} characters long."
+        )));
+    }
+    if let Some(reply_to) = form.reply_to {
+        if form.comment_type == CommentType::Command {
+            return Err(Error::AppError(
+                "Commands can't be replies to other comments. Post it as a top-level command instead."
+                    .to_string(),
+            ));
+        }
+        let (same_post,): (bool,) = sqlx::query_as(
+            r#"
+            select exists(
+              select 1
+              from quest_comment
+              where id = $1 and quest_post = $2
+              limit 1
+            )
+            "#,
+        )
+        .bind(reply_to)
+        .bind(post_id)
+        .fetch_one(&mut *connection)
+        .await
+        .context("Failed to check comment being replied to")?;
+        if !same_post {
+            return Err(Error::AppError(
+                "The comment being replied to doesn't exist on this post.".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validates and records a new comment.
+async fn insert_comment(
+    app_state: &AppState,
+    account_id: Uuid,
+    current_profile: &Option<ProfileRenderInfo>,
+    post_id: Uuid,
+    form: &CommentForm,
+) -> Result<()> {
+    let profile = current_profile
+        .as_ref()
+        .ok_or_else(|| Error::AppError("Choose a profile before commenting.".to_string()))?;
+
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+    validate_comment(&mut transaction, post_id, form).await?;
+
+    sqlx::query(
+        r#"
+        insert into quest_comment
+          (id, commenter, commenter_profile, created_at, quest_post, reply_to, comment_type, body)
+        values (
+          $1,
+          $2,
+          (select id from profile where username = $3 and account_id = $2),
+          current_timestamp,
+          $4,
+          $5,
+          $6,
+          $7
+        )
+        "#,
+    )
+    .bind(Uuid::now_v6(&app_state.uuid_seed))
+    .bind(account_id)
+    .bind(&profile.username)
+    .bind(post_id)
+    .bind(form.reply_to)
+    .bind(form.comment_type)
+    .bind(&form.body)
+    .execute(&mut *transaction)
+    .await
+    .db_context("Failed to post comment")?;
+
+    transaction.commit().await.context("Failed to commit")?;
+    Ok(())
+}
+
+#[post("/{slug}/post/{post_id}/comments")]
+async fn post_comment(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String, Uuid)>,
+    form: web::Form<CommentForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug, post_id) = info.into_inner();
+    let SessionInfo {
+        account_id,
+        current_profile,
+        ..
+    } = app_state.require_session(request).await?;
+    let access = QuestAccess::load(&app_state.db_pool, &username, &slug, Some(account_id)).await?;
+    access.check_comment(Some(account_id))?;
+    let (post_id, post_state) =
+        find_post(&app_state.db_pool, &access, Some(account_id), post_id).await?;
+
+    // Validation errors are shown inline, since this is injected via HTMX.
+    let error = match insert_comment(&app_state, account_id, &current_profile, post_id, &form).await
+    {
+        Ok(()) => None,
+        Err(Error::AppError(message)) => Some(message),
+        Err(err) => return Err(err),
+    };
+
+    let comments = fetch_comments(&app_state.db_pool, post_id).await?;
+    Ok(CommentsTemplate {
+        username: &username,
+        slug: &slug,
+        post_id: &post_id,
+        comments: &comments,
+        closed_notice: closed_notice(
+            post_state,
+            &access,
+            Some(account_id),
+            current_profile.is_some(),
+        ),
+        accepts_commands: post_state.accepts_commands(),
+        error: &error,
+    }
+    .to_response())
+}
@@ -1,9 +1,15 @@
+mod comment;
+mod profile;
 mod view;
+mod vote;
 
 use actix_web::dev::ServiceFactory;
 use actix_web::dev::ServiceRequest;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    let scope = comment::add_routes(scope);
+    let scope = profile::add_routes(scope);
     let scope = view::add_routes(scope);
+    let scope = vote::add_routes(scope);
     scope
 }
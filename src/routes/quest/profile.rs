@@ -0,0 +1,57 @@
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(view_profile)
+}
+
+/// Output object for the profile being viewed.
+#[derive(sqlx::FromRow, Debug)]
+struct ViewedProfile {
+    display_name: Option<String>,
+    bio: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "quest/profile.html")]
+struct ViewProfileTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    username: &'a str,
+    profile: &'a ViewedProfile,
+}
+
+/// A profile's public page, which is where profile mentions link to. Quests
+/// belong to accounts rather than profiles, so they aren't listed, which
+/// would show which profiles share an account.
+#[get("")]
+async fn view_profile(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String,)>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username,) = info.into_inner();
+    let session_info = app_state.get_session(request).await.transpose()?;
+
+    let profile: ViewedProfile = sqlx::query_as(
+        r#"
+        select display_name, bio
+        from profile
+        where username = $1
+        "#,
+    )
+    .bind(&username)
+    .fetch_optional(&app_state.db_pool)
+    .await
+    .context("Failed to fetch profile")?
+    .ok_or_else(|| Error::NotFoundError(format!("The profile @{username} doesn't exist.")))?;
+
+    Ok(ViewProfileTemplate {
+        config: &app_state.config,
+        logged_in: session_info.is_some(),
+        current_profile: &session_info.and_then(|session_info| session_info.current_profile),
+        username: &username,
+        profile: &profile,
+    }
+    .to_response())
+}
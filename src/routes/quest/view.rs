@@ -1,14 +1,305 @@
+use chrono::{DateTime, Utc};
+
+use crate::model::{PostState, QuestPublishState};
+use crate::permissions::QuestAccess;
 use crate::routes::prelude::*;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
-    scope.service(view_quest)
+    scope
+        .service(view_quest)
+        .service(view_post)
+        .service(view_threadmarks)
 }
 
+/// Number of posts shown per page of a quest.
+const POSTS_PER_PAGE: i64 = 20;
+
 /// Output object for quest list query.
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq)]
 struct ListPost {
-    title: String,
+    id: Uuid,
+    title: Option<String>,
     body_html: String,
+    state: PostState,
+    winning_command: Option<Uuid>,
+    edited_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+    chapter_title: Option<String>,
+}
+
+impl ListPost {
+    /// Whether the voting panel should be shown, i.e. voting is either ongoing
+    /// or has results.
+    fn has_votes(&self) -> bool {
+        self.state.accepts_votes()
+            || (self.state == PostState::Finalized && self.winning_command.is_some())
+    }
+}
+
+/// Output object for the quest being viewed, along with its QM's profile.
+#[derive(sqlx::FromRow, Debug)]
+struct ViewedQuest {
+    title: String,
+    publish_state: QuestPublishState,
+    long_description_html: Option<String>,
+    qm_display_name: String,
+}
+
+/// Output object for the table of contents query.
+#[derive(sqlx::FromRow, Debug)]
+struct TocChapter {
+    title: String,
+    /// First published post of the chapter.
+    first_post: Uuid,
+}
+
+/// Checks that the quest can be viewed and loads it.
+async fn load_quest(
+    db_pool: &sqlx::postgres::PgPool,
+    username: &str,
+    slug: &str,
+    account_id: Option<Uuid>,
+) -> Result<(QuestAccess, ViewedQuest)> {
+    let access = QuestAccess::load(db_pool, username, slug, account_id).await?;
+    access.check_view(account_id)?;
+
+    let quest: ViewedQuest = sqlx::query_as(
+        r#"
+        select
+          title,
+          publish_state,
+          long_description_html,
+          profile.display_name as qm_display_name
+        from quest
+          join profile on profile.account_id = quest.questmaster
+        where quest.id = $1
+          and profile.username = $2
+        "#,
+    )
+    .bind(access.quest_id)
+    .bind(username)
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to fetch quest")?;
+
+    Ok((access, quest))
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    /// Show the posts after this one.
+    after: Option<Uuid>,
+    /// Show the posts starting from this one, e.g. for a chapter.
+    from: Option<Uuid>,
+    /// Show the posts before this one.
+    before: Option<Uuid>,
+    /// Show the last page.
+    #[serde(default)]
+    latest: bool,
+}
+
+/// A page of published posts, in reading order.
+struct Page {
+    posts: Vec<ListPost>,
+    has_previous: bool,
+    has_next: bool,
+}
+
+impl Page {
+    /// Post to show the previous page before, if there is one.
+    fn previous(&self) -> Option<Uuid> {
+        self.posts
+            .first()
+            .filter(|_| self.has_previous)
+            .map(|post| post.id)
+    }
+
+    /// Post to show the next page after, if there is one.
+    fn next(&self) -> Option<Uuid> {
+        self.posts
+            .last()
+            .filter(|_| self.has_next)
+            .map(|post| post.id)
+    }
+}
+
+/// Whether there is a published post before (or after) the given position.
+async fn published_post_beyond(
+    db_pool: &sqlx::postgres::PgPool,
+    quest_id: Uuid,
+    post: &ListPost,
+    before: bool,
+) -> Result<bool> {
+    let (exists,): (bool,) = sqlx::query_as(
+        r#"
+        select exists(
+          select 1
+          from quest_post
+          where quest = $1
+            and published_at is not null
+            and case
+              when $4 then (published_at, id) < ($2, $3)
+              else (published_at, id) > ($2, $3)
+            end
+          limit 1
+        )
+        "#,
+    )
+    .bind(quest_id)
+    .bind(post.published_at)
+    .bind(post.id)
+    .bind(before)
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to check for more posts")?;
+    Ok(exists)
+}
+
+/// Fetches a page of published posts using keyset pagination on
+/// `(published_at, id)`, so that deep pages are as cheap as the first one.
+async fn fetch_page(
+    db_pool: &sqlx::postgres::PgPool,
+    quest_id: Uuid,
+    query: &PageQuery,
+) -> Result<Page> {
+    // Pages that end at a post, including the last page, are read backwards.
+    let backwards = query.before.is_some() || query.latest;
+    let mut posts: Vec<ListPost> = if backwards {
+        sqlx::query_as(
+            r#"
+            select
+              quest_post.id,
+              quest_post.title,
+              body_html,
+              state,
+              winning_command,
+              edited_at,
+              published_at,
+              quest_chapter.title as chapter_title
+            from quest_post
+              left join quest_chapter on quest_chapter.id = quest_post.chapter
+            where quest_post.quest = $1
+              and published_at is not null
+              and ($2::uuid is null or (published_at, quest_post.id) < (
+                select published_at, id from quest_post where id = $2 and quest = $1
+              ))
+            order by published_at desc, quest_post.id desc
+            limit $3
+            "#,
+        )
+        .bind(quest_id)
+        .bind(query.before)
+        .bind(POSTS_PER_PAGE + 1)
+        .fetch_all(db_pool)
+        .await
+    } else {
+        sqlx::query_as(
+            r#"
+            select
+              quest_post.id,
+              quest_post.title,
+              body_html,
+              state,
+              winning_command,
+              edited_at,
+              published_at,
+              quest_chapter.title as chapter_title
+            from quest_post
+              left join quest_chapter on quest_chapter.id = quest_post.chapter
+            where quest_post.quest = $1
+              and published_at is not null
+              and ($2::uuid is null or (published_at, quest_post.id) > (
+                select published_at, id from quest_post where id = $2 and quest = $1
+              ))
+              and ($3::uuid is null or (published_at, quest_post.id) >= (
+                select published_at, id from quest_post where id = $3 and quest = $1
+              ))
+            order by published_at asc, quest_post.id asc
+            limit $4
+            "#,
+        )
+        .bind(quest_id)
+        .bind(query.after)
+        .bind(query.from)
+        .bind(POSTS_PER_PAGE + 1)
+        .fetch_all(db_pool)
+        .await
+    }
+    .context("Failed to fetch quest posts")?;
+
+    // One extra post was fetched to tell whether there is more in the
+    // direction we were reading.
+    let more = posts.len() as i64 > POSTS_PER_PAGE;
+    posts.truncate(POSTS_PER_PAGE as usize);
+    if backwards {
+        posts.reverse();
+    }
+
+    let (has_previous, has_next) = match (posts.first(), posts.last()) {
+        (Some(first), Some(last)) => {
+            if backwards {
+                let has_next =
+                    !query.latest && published_post_beyond(db_pool, quest_id, last, false).await?;
+                (more, has_next)
+            } else {
+                let has_previous = published_post_beyond(db_pool, quest_id, first, true).await?;
+                (has_previous, more)
+            }
+        }
+        _ => (false, false),
+    };
+
+    Ok(Page {
+        posts,
+        has_previous,
+        has_next,
+    })
+}
+
+/// Chapters that have published posts, in reading order.
+async fn fetch_toc(db_pool: &sqlx::postgres::PgPool, quest_id: Uuid) -> Result<Vec<TocChapter>> {
+    Ok(sqlx::query_as(
+        r#"
+        select quest_chapter.title, first_post.id as first_post
+        from quest_chapter
+          join lateral (
+            select id, published_at
+            from quest_post
+            where chapter = quest_chapter.id
+              and published_at is not null
+            order by published_at asc, id asc
+            limit 1
+          ) first_post on true
+        where quest_chapter.quest = $1
+        order by first_post.published_at asc, first_post.id asc
+        "#,
+    )
+    .bind(quest_id)
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch table of contents")?)
+}
+
+/// The account's setting for showing comments. Guests always see them.
+async fn fetch_show_comments(
+    db_pool: &sqlx::postgres::PgPool,
+    account_id: Option<Uuid>,
+) -> Result<bool> {
+    let Some(account_id) = account_id else {
+        return Ok(true);
+    };
+    let (show_comments,): (bool,) = sqlx::query_as(
+        r#"
+        select show_comments
+        from account
+        where id = $1
+        "#,
+    )
+    .bind(account_id)
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to fetch comment visibility")?;
+    Ok(show_comments)
 }
 
 #[derive(Template)]
@@ -17,45 +308,276 @@ struct ViewQuestTemplate<'a> {
     config: &'a AppConfig,
     logged_in: bool,
     current_profile: &'a Option<ProfileRenderInfo>,
-    title: &'a String,
-    posts: &'a Vec<ListPost>,
+    quest: &'a ViewedQuest,
+    username: &'a str,
+    slug: &'a str,
+    toc: &'a Vec<TocChapter>,
+    page: &'a Page,
+    /// Unpublished posts, only shown to the QM on the last page.
+    drafts: &'a Vec<ListPost>,
+    /// Whether to show comment threads, or only the QM's posts.
+    show_comments: bool,
+    /// This page, to come back to after toggling comments.
+    return_to: &'a str,
 }
 
 #[get("/{slug}")]
 async fn view_quest(
     app_state: web::Data<AppState>,
     info: web::Path<(String, String)>,
+    query: web::Query<PageQuery>,
     request: HttpRequest,
 ) -> Result<impl Responder> {
     let (username, slug) = info.into_inner();
+    let return_to = request.uri().to_string();
+    let session_info = app_state.get_session(request).await.transpose()?;
+    let account_id = session_info.as_ref().map(|info| info.account_id);
+    let (access, quest) = load_quest(&app_state.db_pool, &username, &slug, account_id).await?;
+
+    let (page, toc, show_comments): (Page, Vec<TocChapter>, bool) = try_join!(
+        fetch_page(&app_state.db_pool, access.quest_id, &query),
+        fetch_toc(&app_state.db_pool, access.quest_id),
+        fetch_show_comments(&app_state.db_pool, account_id),
+    )?;
+
+    let drafts: Vec<ListPost> = if access.is_questmaster(account_id) && !page.has_next {
+        sqlx::query_as(
+            r#"
+            select
+              quest_post.id,
+              quest_post.title,
+              body_html,
+              state,
+              winning_command,
+              edited_at,
+              published_at,
+              quest_chapter.title as chapter_title
+            from quest_post
+              left join quest_chapter on quest_chapter.id = quest_post.chapter
+            where quest_post.quest = $1
+              and published_at is null
+            order by quest_post.created_at asc
+            "#,
+        )
+        .bind(access.quest_id)
+        .fetch_all(&app_state.db_pool)
+        .await
+        .context("Failed to fetch drafts")?
+    } else {
+        Vec::new()
+    };
+
+    Ok(ViewQuestTemplate {
+        config: &app_state.config,
+        logged_in: session_info.is_some(),
+        current_profile: &session_info.and_then(|session_info| session_info.current_profile),
+        quest: &quest,
+        username: &username,
+        slug: &slug,
+        toc: &toc,
+        page: &page,
+        drafts: &drafts,
+        show_comments,
+        return_to: &return_to,
+    }
+    .to_response())
+}
+
+/// Output object for the permalink query.
+#[derive(sqlx::FromRow, Debug)]
+struct PermalinkPost {
+    #[sqlx(flatten)]
+    post: ListPost,
+    /// Plain-text summary for link previews.
+    excerpt: String,
+}
+
+#[derive(Template)]
+#[template(path = "quest/post.html")]
+struct ViewPostTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    quest: &'a ViewedQuest,
+    username: &'a str,
+    slug: &'a str,
+    post: &'a ListPost,
+    excerpt: &'a str,
+    previous: Option<Uuid>,
+    next: Option<Uuid>,
+    /// Always true, since the permalink is where a post's thread lives.
+    show_comments: bool,
+}
+
+/// Permalink to a single post.
+#[get("/{slug}/post/{post_id}")]
+async fn view_post(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String, Uuid)>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug, post_id) = info.into_inner();
     let session_info = app_state.get_session(request).await.transpose()?;
+    let account_id = session_info.as_ref().map(|info| info.account_id);
+    let (access, quest) = load_quest(&app_state.db_pool, &username, &slug, account_id).await?;
 
-    // TODO - Handle user doesn't exist.
-    // TODO - Handle quest doesn't exist.
-    // TODO - Handle no permission to view.
+    let PermalinkPost { post, excerpt } = sqlx::query_as(
+        r#"
+        select
+          quest_post.id,
+          quest_post.title,
+          body_html,
+          coalesce(excerpt, '') as excerpt,
+          state,
+          winning_command,
+          edited_at,
+          published_at,
+          quest_chapter.title as chapter_title
+        from quest_post
+          left join quest_chapter on quest_chapter.id = quest_post.chapter
+        where quest_post.quest = $1
+          and quest_post.id = $2
+          and (published_at is not null or $3)
+        "#,
+    )
+    .bind(access.quest_id)
+    .bind(post_id)
+    .bind(access.is_questmaster(account_id))
+    .fetch_optional(&app_state.db_pool)
+    .await
+    .context("Failed to fetch quest post")?
+    .ok_or_else(|| Error::NotFoundError("That quest post doesn't exist.".to_string()))?;
 
-    let posts: Vec<ListPost> = sqlx::query_as(
+    let (previous, next): (Option<Uuid>, Option<Uuid>) = sqlx::query_as(
         r#"
-        select quest_post.title, quest_post.body_html
-        from quest
-          join profile on questmaster = account_id
-          join quest_post on quest.id = quest_post.quest
-        where profile.username = $1
-          and quest.slug = $2
+        select
+          (
+            select id
+            from quest_post
+            where quest = $1
+              and published_at is not null
+              and (published_at, id) < ($2, $3)
+            order by published_at desc, id desc
+            limit 1
+          ) as previous,
+          (
+            select id
+            from quest_post
+            where quest = $1
+              and published_at is not null
+              and (published_at, id) > ($2, $3)
+            order by published_at asc, id asc
+            limit 1
+          ) as next
         "#,
     )
-    .bind(username)
-    .bind(slug)
+    .bind(access.quest_id)
+    .bind(post.published_at)
+    .bind(post.id)
+    .fetch_one(&app_state.db_pool)
+    .await
+    .context("Failed to fetch adjacent posts")?;
+
+    Ok(ViewPostTemplate {
+        config: &app_state.config,
+        logged_in: session_info.is_some(),
+        current_profile: &session_info.and_then(|session_info| session_info.current_profile),
+        quest: &quest,
+        username: &username,
+        slug: &slug,
+        post: &post,
+        excerpt: &excerpt,
+        previous,
+        next,
+        show_comments: true,
+    }
+    .to_response())
+}
+
+/// Output object for the threadmarks query.
+#[derive(sqlx::FromRow, Debug)]
+struct Threadmark {
+    id: Uuid,
+    title: Option<String>,
+    published_at: DateTime<Utc>,
+    chapter_title: Option<String>,
+}
+
+/// Consecutive threadmarks in the same chapter.
+struct ThreadmarkGroup {
+    chapter_title: Option<String>,
+    /// Threadmarks along with their 1-based position in the quest.
+    threadmarks: Vec<(usize, Threadmark)>,
+}
+
+/// Groups threadmarks into runs of the same chapter, keeping their order.
+fn group_threadmarks(threadmarks: Vec<Threadmark>) -> Vec<ThreadmarkGroup> {
+    let mut groups: Vec<ThreadmarkGroup> = Vec::new();
+    for (i, threadmark) in threadmarks.into_iter().enumerate() {
+        match groups.last_mut() {
+            Some(group) if group.chapter_title == threadmark.chapter_title => {
+                group.threadmarks.push((i + 1, threadmark));
+            }
+            _ => groups.push(ThreadmarkGroup {
+                chapter_title: threadmark.chapter_title.clone(),
+                threadmarks: vec![(i + 1, threadmark)],
+            }),
+        }
+    }
+    groups
+}
+
+#[derive(Template)]
+#[template(path = "quest/threadmarks.html")]
+struct ThreadmarksTemplate<'a> {
+    config: &'a AppConfig,
+    logged_in: bool,
+    current_profile: &'a Option<ProfileRenderInfo>,
+    quest: &'a ViewedQuest,
+    username: &'a str,
+    slug: &'a str,
+    groups: &'a Vec<ThreadmarkGroup>,
+}
+
+/// Compact index of every published QM post, without any player chatter.
+#[get("/{slug}/threadmarks")]
+async fn view_threadmarks(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String)>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug) = info.into_inner();
+    let session_info = app_state.get_session(request).await.transpose()?;
+    let account_id = session_info.as_ref().map(|info| info.account_id);
+    let (access, quest) = load_quest(&app_state.db_pool, &username, &slug, account_id).await?;
+
+    let threadmarks: Vec<Threadmark> = sqlx::query_as(
+        r#"
+        select
+          quest_post.id,
+          quest_post.title,
+          published_at,
+          quest_chapter.title as chapter_title
+        from quest_post
+          left join quest_chapter on quest_chapter.id = quest_post.chapter
+        where quest_post.quest = $1
+          and published_at is not null
+        order by published_at asc, quest_post.id asc
+        "#,
+    )
+    .bind(access.quest_id)
     .fetch_all(&app_state.db_pool)
     .await
-    .context("Failed to fetch quests")?;
+    .context("Failed to fetch threadmarks")?;
 
-    Ok(ViewQuestTemplate {
+    Ok(ThreadmarksTemplate {
         config: &app_state.config,
         logged_in: session_info.is_some(),
         current_profile: &session_info.and_then(|session_info| session_info.current_profile),
-        title: &"Placeholder".to_string(),
-        posts: &posts,
+        quest: &quest,
+        username: &username,
+        slug: &slug,
+        groups: &group_threadmarks(threadmarks),
     }
     .to_response())
 }
@@ -0,0 +1,286 @@
+use crate::model::{PostState, VoteMode};
+use crate::permissions::QuestAccess;
+use crate::routes::prelude::*;
+
+pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(view_votes).service(cast_vote)
+}
+
+/// Output object for the post being voted on.
+#[derive(sqlx::FromRow, Debug)]
+struct VotePost {
+    id: Uuid,
+    state: PostState,
+    vote_mode: VoteMode,
+    winning_command: Option<Uuid>,
+}
+
+impl VotePost {
+    fn is_winner(&self, command: &Uuid) -> bool {
+        self.winning_command.as_ref() == Some(command)
+    }
+}
+
+/// Output object for the tally query.
+#[derive(sqlx::FromRow, Debug)]
+struct Tally {
+    id: Uuid,
+    body: String,
+    username: Option<String>,
+    votes: i64,
+    voted: bool,
+}
+
+#[derive(Template)]
+#[template(path = "quest/votes.html")]
+struct VotesTemplate<'a> {
+    username: &'a str,
+    slug: &'a str,
+    post: &'a VotePost,
+    tallies: &'a Vec<Tally>,
+    total_votes: i64,
+    logged_in: bool,
+    can_vote: bool,
+    error: &'a Option<String>,
+}
+
+async fn find_post(
+    db_pool: &sqlx::postgres::PgPool,
+    access: &QuestAccess,
+    account_id: Option<Uuid>,
+    post_id: Uuid,
+) -> Result<VotePost> {
+    sqlx::query_as(
+        r#"
+        select id, state, vote_mode, winning_command
+        from quest_post
+        where quest = $1 and id = $2
+          and (published_at is not null or $3)
+        "#,
+    )
+    .bind(access.quest_id)
+    .bind(post_id)
+    .bind(access.is_questmaster(account_id))
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch quest post")?
+    .ok_or_else(|| Error::NotFoundError("That quest post doesn't exist.".to_string()))
+}
+
+/// Votes per command on a post, most popular first. Commands excluded by the
+/// QM are left out entirely.
+async fn fetch_tallies(
+    db_pool: &sqlx::postgres::PgPool,
+    post_id: Uuid,
+    account_id: Option<Uuid>,
+) -> Result<Vec<Tally>> {
+    Ok(sqlx::query_as(
+        r#"
+        select
+          quest_comment.id,
+          quest_comment.body,
+          profile.username,
+          count(quest_vote.voter) as votes,
+          coalesce(bool_or(quest_vote.voter = $2), false) as voted
+        from quest_comment
+          left join profile on commenter_profile = profile.id
+          left join quest_vote on quest_vote.command = quest_comment.id
+        where quest_comment.quest_post = $1
+          and comment_type = 'command'::quest_comment_type
+          and not qm_excluded
+        group by quest_comment.id, profile.username
+        order by votes desc, quest_comment.created_at asc
+        "#,
+    )
+    .bind(post_id)
+    .bind(account_id)
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch vote tallies")?)
+}
+
+async fn render_votes(
+    app_state: &AppState,
+    username: &str,
+    slug: &str,
+    access: &QuestAccess,
+    post: &VotePost,
+    account_id: Option<Uuid>,
+    error: &Option<String>,
+) -> Result<HttpResponse> {
+    let tallies = fetch_tallies(&app_state.db_pool, post.id, account_id).await?;
+    Ok(VotesTemplate {
+        username,
+        slug,
+        post,
+        total_votes: tallies.iter().map(|tally| tally.votes).sum(),
+        tallies: &tallies,
+        logged_in: account_id.is_some(),
+        can_vote: access.can_comment(account_id),
+        error,
+    }
+    .to_response())
+}
+
+/// Voting panel with live tallies, for injection via HTMX.
+#[get("/{slug}/post/{post_id}/votes")]
+async fn view_votes(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String, Uuid)>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug, post_id) = info.into_inner();
+    let session_info = app_state.get_session(request).await.transpose()?;
+    let account_id = session_info.map(|info| info.account_id);
+    let access = QuestAccess::load(&app_state.db_pool, &username, &slug, account_id).await?;
+    access.check_view(account_id)?;
+    let post = find_post(&app_state.db_pool, &access, account_id, post_id).await?;
+
+    render_votes(
+        &app_state, &username, &slug, &access, &post, account_id, &None,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct VoteForm {
+    command: Uuid,
+}
+
+/// Toggles the voter's vote for a command. In single choice mode, voting for
+/// a command also removes the voter's vote for any other command.
+async fn toggle_vote(
+    app_state: &AppState,
+    post: &VotePost,
+    account_id: Uuid,
+    command: Uuid,
+) -> Result<()> {
+    let mut transaction = app_state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to create transaction")?;
+
+    // Lock the post, so that the vote can't be closed while this one is being
+    // counted, and so that concurrent votes from the same voter can't both
+    // land in single choice mode.
+    let (state, vote_mode): (PostState, VoteMode) = sqlx::query_as(
+        r#"
+        select state, vote_mode
+        from quest_post
+        where id = $1
+        for update
+        "#,
+    )
+    .bind(post.id)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to lock post")?;
+    if !state.accepts_votes() {
+        return Err(Error::AppError(
+            "This post isn't accepting votes.".to_string(),
+        ));
+    }
+
+    let (votable,): (bool,) = sqlx::query_as(
+        r#"
+        select exists(
+          select 1
+          from quest_comment
+          where id = $1
+            and quest_post = $2
+            and comment_type = 'command'::quest_comment_type
+            and not qm_excluded
+          limit 1
+        )
+        "#,
+    )
+    .bind(command)
+    .bind(post.id)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to check command")?;
+    if !votable {
+        return Err(Error::AppError(
+            "That command can't be voted for.".to_string(),
+        ));
+    }
+
+    let removed = sqlx::query(
+        r#"
+        delete from quest_vote
+        where voter = $1 and command = $2
+        "#,
+    )
+    .bind(account_id)
+    .bind(command)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove vote")?
+    .rows_affected();
+
+    if removed == 0 {
+        if vote_mode == VoteMode::Single {
+            sqlx::query(
+                r#"
+                delete from quest_vote
+                where voter = $1 and quest_post = $2
+                "#,
+            )
+            .bind(account_id)
+            .bind(post.id)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to remove previous vote")?;
+        }
+
+        sqlx::query(
+            r#"
+            insert into quest_vote (voter, quest_post, command)
+            values ($1, $2, $3)
+            on conflict do nothing
+            "#,
+        )
+        .bind(account_id)
+        .bind(post.id)
+        .bind(command)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to record vote")?;
+    }
+
+    transaction.commit().await.context("Failed to commit")?;
+    Ok(())
+}
+
+#[post("/{slug}/post/{post_id}/votes")]
+async fn cast_vote(
+    app_state: web::Data<AppState>,
+    info: web::Path<(String, String, Uuid)>,
+    form: web::Form<VoteForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let (username, slug, post_id) = info.into_inner();
+    let SessionInfo { account_id, .. } = app_state.require_session(request).await?;
+    let access = QuestAccess::load(&app_state.db_pool, &username, &slug, Some(account_id)).await?;
+    access.check_comment(Some(account_id))?;
+    let post = find_post(&app_state.db_pool, &access, Some(account_id), post_id).await?;
+
+    // Validation errors are shown inline, since this is injected via HTMX.
+    let error = match toggle_vote(&app_state, &post, account_id, form.command).await {
+        Ok(()) => None,
+        Err(Error::AppError(message)) => Some(message),
+        Err(err) => return Err(err),
+    };
+
+    render_votes(
+        &app_state,
+        &username,
+        &slug,
+        &access,
+        &post,
+        Some(account_id),
+        &error,
+    )
+    .await
+}
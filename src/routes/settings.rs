@@ -1,7 +1,11 @@
+use crate::permissions::require_own_profile;
 use crate::routes::prelude::*;
 
 pub fn add_routes(scope: actix_web::Scope) -> actix_web::Scope {
-    scope.service(view).service(update)
+    scope
+        .service(view)
+        .service(update)
+        .service(set_show_comments)
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq)]
@@ -27,6 +31,7 @@ struct SettingsTemplate<'a> {
 struct Settings {
     ask_for_profile_on_login: bool,
     default_profile_username: Option<String>,
+    show_comments: bool,
 }
 
 #[get("/")]
@@ -51,7 +56,8 @@ async fn view_fn(
             r#"
             select
               ask_for_profile_on_login,
-              profile.username as default_profile_username
+              profile.username as default_profile_username,
+              show_comments
             from
               account
               left join profile on account.default_profile = profile.id
@@ -89,6 +95,11 @@ async fn view_fn(
 enum SettingsForm {
     /// Account change.
     Account { default_profile: String },
+    /// Reading preferences change.
+    Reading {
+        /// Checkbox, only present if checked.
+        show_comments: Option<String>,
+    },
     /// Profile details change.
     ProfileDetails {
         username: String,
@@ -152,16 +163,20 @@ async fn update(
                 }
                 _ => {
                     validation::username(default_profile)?;
+                    let profile_id = require_own_profile(
+                        &app_state.db_pool,
+                        session_info.account_id,
+                        default_profile,
+                    )
+                    .await?;
                     if sqlx::query(
                         r#"
                         update account
-                        set ask_for_profile_on_login = false, default_profile = (
-                          select id from profile where username = $1 limit 1
-                        )
+                        set ask_for_profile_on_login = false, default_profile = $1
                         where id = $2
                         "#,
                     )
-                    .bind(default_profile)
+                    .bind(profile_id)
                     .bind(session_info.account_id)
                     .execute(&app_state.db_pool)
                     .await
@@ -176,24 +191,33 @@ async fn update(
                 }
             }
         }
+        SettingsForm::Reading { show_comments } => {
+            let show_comments = show_comments.is_some();
+            save_show_comments(&app_state, session_info.account_id, show_comments).await?;
+            messages.push(format!(
+                "{} comments between quest posts",
+                if show_comments { "Showing" } else { "Hiding" }
+            ));
+        }
         SettingsForm::ProfileDetails {
             username,
             display_name,
             bio,
         } => {
             trace!("Bio: {}", bio);
-            // TODO: verify ownership before update.
             validation::username(username.as_str())?;
+            let profile_id =
+                require_own_profile(&app_state.db_pool, session_info.account_id, &username).await?;
             if sqlx::query(
                 r#"
                 update profile
                 set display_name = $1, bio = $2
-                where username = $3
+                where id = $3
                 "#,
             )
             .bind(display_name)
             .bind(bio)
-            .bind(&username)
+            .bind(profile_id)
             .execute(&app_state.db_pool)
             .await
             .context("Failed to set profile default")?
@@ -209,19 +233,23 @@ async fn update(
             original_username,
             username,
         } => {
-            // TODO: verify ownership before update.
             validation::username(username.as_str())?;
             validation::username(original_username.as_str())?;
+            let profile_id = require_own_profile(
+                &app_state.db_pool,
+                session_info.account_id,
+                &original_username,
+            )
+            .await?;
             if sqlx::query(
                 r#"
                 update profile
                 set username = $1
-                where username = $2
+                where id = $2
                 "#,
             )
             .bind(&username)
-            .bind(&original_username)
-            .bind(session_info.account_id)
+            .bind(profile_id)
             .execute(&app_state.db_pool)
             .await
             .context("Failed to set username")?
@@ -284,3 +312,49 @@ async fn update(
 
     view_fn(app_state, session_info, &messages).await
 }
+
+async fn save_show_comments(
+    app_state: &AppState,
+    account_id: Uuid,
+    show_comments: bool,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        update account
+        set show_comments = $1
+        where id = $2
+        "#,
+    )
+    .bind(show_comments)
+    .bind(account_id)
+    .execute(&app_state.db_pool)
+    .await
+    .context("Failed to set comment visibility")?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ShowCommentsForm {
+    show_comments: bool,
+    /// Page to go back to afterwards.
+    return_to: String,
+}
+
+/// Quick toggle for showing comments, used from quest pages.
+#[post("/show_comments")]
+async fn set_show_comments(
+    app_state: web::Data<AppState>,
+    form: web::Form<ShowCommentsForm>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let session_info = app_state.require_session(request).await?;
+    save_show_comments(&app_state, session_info.account_id, form.show_comments).await?;
+
+    // Only redirect within the site.
+    let return_to = if validation::is_site_path(&form.return_to) {
+        form.return_to.as_str()
+    } else {
+        "/settings/"
+    };
+    Ok(web::Redirect::to(return_to.to_string()).see_other())
+}
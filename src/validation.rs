@@ -16,3 +16,13 @@ pub fn username(username: &str) -> Result<()> {
         Ok(())
     }
 }
+
+/// Whether a URL is a path on this site, so that it's safe to redirect to or
+/// embed. Browsers treat a backslash like a slash and ignore tabs and
+/// newlines, so `/\evil.com` and `/\t/evil.com` would both go to another
+/// site, just like `//evil.com`.
+pub fn is_site_path(url: &str) -> bool {
+    url.starts_with('/')
+        && !url.starts_with("//")
+        && !url.contains(|c: char| c == '\\' || c.is_whitespace() || c.is_control())
+}